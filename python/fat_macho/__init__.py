@@ -0,0 +1,23 @@
+from ._fat_macho import (
+    BitcodeError,
+    DuplicatedArchError,
+    FatMachoError,
+    FatWriter,
+    IncompatiblePlatformError,
+    InvalidMachOError,
+    NotFatBinaryError,
+    __version__,
+    main,
+)
+
+__all__ = [
+    "BitcodeError",
+    "DuplicatedArchError",
+    "FatMachoError",
+    "FatWriter",
+    "IncompatiblePlatformError",
+    "InvalidMachOError",
+    "NotFatBinaryError",
+    "__version__",
+    "main",
+]
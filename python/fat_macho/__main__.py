@@ -0,0 +1,11 @@
+import sys
+
+from ._fat_macho import main
+
+
+def run() -> None:
+    sys.exit(main())
+
+
+if __name__ == "__main__":
+    run()
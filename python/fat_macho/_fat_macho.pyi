@@ -0,0 +1,64 @@
+import os
+from types import TracebackType
+from typing import Any, BinaryIO, Dict, Iterator, List, Optional, Tuple, Type, Union, overload
+
+from typing_extensions import Buffer
+
+StrPath = Union[str, os.PathLike[str]]
+
+
+class FatMachoError(ValueError): ...
+class NotFatBinaryError(FatMachoError): ...
+class DuplicatedArchError(FatMachoError):
+    arch: str
+class InvalidMachOError(FatMachoError): ...
+class BitcodeError(FatMachoError): ...
+class IncompatiblePlatformError(FatMachoError):
+    arch: str
+    platform: str
+    existing_arch: str
+    existing_platform: str
+
+
+class FatWriter:
+    fat64: bool
+    mixed_platforms: bool
+    def __init__(
+        self,
+        path: Optional[StrPath] = None,
+        *,
+        fat64: bool = False,
+        mixed_platforms: bool = False,
+    ): ...
+    def add(self, data: Union[Buffer, StrPath]): ...
+    def add_file(self, path: StrPath): ...
+    def add_raw(self, arch: str, align: int, data: Union[Buffer, StrPath]): ...
+    def replace(self, data: Union[Buffer, StrPath]) -> List[bytes]: ...
+    def remove(self, arch: str) -> Optional[bytes]: ...
+    def exists(self, arch: str) -> bool: ...
+    def get(self, arch: str) -> Optional[bytes]: ...
+    @property
+    def arches(self) -> List[str]: ...
+    def set_align(self, arch: str, align: int): ...
+    def __len__(self) -> int: ...
+    def __contains__(self, arch: str) -> bool: ...
+    def __iter__(self) -> Iterator[Tuple[str, bytes]]: ...
+    def __enter__(self) -> "FatWriter": ...
+    def __exit__(
+        self,
+        exc_type: Optional[Type[BaseException]],
+        exc_value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> bool: ...
+    def write_to(self, path: StrPath): ...
+    def plan(self) -> Dict[str, Any]: ...
+    @overload
+    def generate(self) -> bytes: ...
+    @overload
+    def generate(self, file: BinaryIO) -> None: ...
+
+
+__version__: str
+
+
+def main(argv: Optional[List[str]] = None) -> int: ...
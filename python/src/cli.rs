@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::PathBuf;
+
+use fat_macho_rs::{Arch, Error, FatReader, FatWriter};
+use pyo3::prelude::*;
+
+const USAGE: &str = "usage: fat-macho <command> [<args>]
+
+commands:
+  create [--fat64] -o OUTPUT INPUT...         create a fat binary from thin or fat inputs
+  extract [--fat64] -o OUTPUT INPUT ARCH...   create a fat binary with only the given architectures
+  thin -o OUTPUT INPUT ARCH                   extract the thin binary of an architecture
+  remove [--fat64] -o OUTPUT INPUT ARCH...    remove architectures from a fat binary
+  info INPUT...                               show the architectures of the inputs
+  verify INPUT ARCH...                        exit with status 0 if all architectures exist
+";
+
+/// Parsed command line arguments of a subcommand
+#[derive(Debug, Default)]
+struct Args {
+    output: Option<PathBuf>,
+    fat64: bool,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(argv: &[String]) -> Result<Self, String> {
+        let mut args = Args::default();
+        let mut iter = argv.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-o" | "--output" => match iter.next() {
+                    Some(output) => args.output = Some(PathBuf::from(output)),
+                    None => return Err(format!("{} requires an argument", arg)),
+                },
+                "--fat64" => args.fat64 = true,
+                "--" => args.positional.extend(iter.by_ref().cloned()),
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("unknown option {}", arg))
+                }
+                _ => args.positional.push(arg.clone()),
+            }
+        }
+        Ok(args)
+    }
+
+    fn output(&self) -> Result<&PathBuf, String> {
+        self.output
+            .as_ref()
+            .ok_or_else(|| "missing -o OUTPUT".to_string())
+    }
+
+    /// Split positional arguments into the input and at least `min_arches` architectures
+    fn input_and_arches(&self, min_arches: usize) -> Result<(&str, &[String]), String> {
+        match self.positional.split_first() {
+            Some((input, arches)) if arches.len() >= min_arches => Ok((input, arches)),
+            Some(_) => Err("missing architecture".to_string()),
+            None => Err("missing INPUT".to_string()),
+        }
+    }
+}
+
+/// Outcome of a failed command
+enum Failure {
+    /// Invalid command line arguments, exit status 2
+    Usage(String),
+    /// Command failed, exit status 1
+    Error(String),
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        Failure::Error(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(err: std::io::Error) -> Self {
+        Failure::Error(err.to_string())
+    }
+}
+
+fn write_stream(py: Python, stream: &str, text: &str) -> PyResult<()> {
+    py.import("sys")?
+        .getattr(stream)?
+        .call_method1("write", (text,))?;
+    Ok(())
+}
+
+/// Load a thin or fat binary into a writer
+fn load(path: &str) -> Result<FatWriter, Failure> {
+    let data = fs::read(path).map_err(|e| Failure::Error(format!("{}: {}", path, e)))?;
+    let mut fat = FatWriter::new();
+    fat.add(data)
+        .map_err(|e| Failure::Error(format!("{}: {}", path, e)))?;
+    Ok(fat)
+}
+
+fn check_arches(fat: &FatWriter, path: &str, arches: &[String]) -> Result<(), Failure> {
+    match arches.iter().find(|arch| !fat.exists(arch)) {
+        Some(arch) => Err(Failure::Error(format!(
+            "{} does not contain the {} architecture",
+            path, arch
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn create(args: &Args) -> Result<i32, Failure> {
+    let output = args.output().map_err(Failure::Usage)?;
+    if args.positional.is_empty() {
+        return Err(Failure::Usage("missing INPUT".to_string()));
+    }
+    let mut fat = FatWriter::new();
+    fat.set_fat64(args.fat64);
+    for input in &args.positional {
+        let data = fs::read(input).map_err(|e| Failure::Error(format!("{}: {}", input, e)))?;
+        fat.add(data)
+            .map_err(|e| Failure::Error(format!("{}: {}", input, e)))?;
+    }
+    fat.write_to_file(output)?;
+    Ok(0)
+}
+
+fn extract(args: &Args) -> Result<i32, Failure> {
+    let output = args.output().map_err(Failure::Usage)?;
+    let (input, arches) = args.input_and_arches(1).map_err(Failure::Usage)?;
+    let mut fat = load(input)?;
+    check_arches(&fat, input, arches)?;
+    let mut extracted = FatWriter::new();
+    extracted.set_fat64(args.fat64);
+    for arch in arches {
+        if let Some(data) = fat.remove(arch) {
+            extracted.add(data)?;
+        }
+    }
+    extracted.write_to_file(output)?;
+    Ok(0)
+}
+
+fn thin(args: &Args) -> Result<i32, Failure> {
+    let output = args.output().map_err(Failure::Usage)?;
+    let (input, arches) = args.input_and_arches(1).map_err(Failure::Usage)?;
+    if arches.len() > 1 {
+        return Err(Failure::Usage(
+            "thin takes exactly one architecture".to_string(),
+        ));
+    }
+    let fat = load(input)?;
+    check_arches(&fat, input, arches)?;
+    let data = fat.get(&arches[0]).unwrap_or_default();
+    fs::write(output, data)?;
+    // Keep the executable bit of the input
+    fs::set_permissions(output, fs::metadata(input)?.permissions())?;
+    Ok(0)
+}
+
+fn remove(args: &Args) -> Result<i32, Failure> {
+    let output = args.output().map_err(Failure::Usage)?;
+    let (input, arches) = args.input_and_arches(1).map_err(Failure::Usage)?;
+    let mut fat = load(input)?;
+    check_arches(&fat, input, arches)?;
+    for arch in arches {
+        fat.remove(arch);
+    }
+    if fat.is_empty() {
+        return Err(Failure::Error("can't remove all architectures".to_string()));
+    }
+    fat.set_fat64(args.fat64);
+    fat.write_to_file(output)?;
+    Ok(0)
+}
+
+fn info(py: Python, args: &Args) -> PyResult<Result<i32, Failure>> {
+    if args.positional.is_empty() {
+        return Ok(Err(Failure::Usage("missing INPUT".to_string())));
+    }
+    for input in &args.positional {
+        let data = match fs::read(input) {
+            Ok(data) => data,
+            Err(e) => return Ok(Err(Failure::Error(format!("{}: {}", input, e)))),
+        };
+        match FatReader::new(&data) {
+            Ok(reader) => {
+                write_stream(
+                    py,
+                    "stdout",
+                    &format!("Fat binary {}: {} architectures\n", input, reader.narches),
+                )?;
+                for arch in reader.iter_arches() {
+                    let name = Arch::new(arch.cputype, arch.cpusubtype)
+                        .name()
+                        .unwrap_or("unknown");
+                    write_stream(
+                        py,
+                        "stdout",
+                        &format!(
+                            "  {:<10} offset {:<10} size {:<10} align 2^{}\n",
+                            name, arch.offset, arch.size, arch.align
+                        ),
+                    )?;
+                }
+            }
+            Err(Error::NotFatBinary) => {
+                let fat = match load(input) {
+                    Ok(fat) => fat,
+                    Err(e) => return Ok(Err(e)),
+                };
+                write_stream(
+                    py,
+                    "stdout",
+                    &format!(
+                        "Non-fat file {}: architecture {}\n",
+                        input,
+                        fat.arches().join(" ")
+                    ),
+                )?;
+            }
+            Err(e) => return Ok(Err(Failure::Error(format!("{}: {}", input, e)))),
+        }
+    }
+    Ok(Ok(0))
+}
+
+fn verify(args: &Args) -> Result<i32, Failure> {
+    let (input, arches) = args.input_and_arches(1).map_err(Failure::Usage)?;
+    let fat = load(input)?;
+    if arches.iter().all(|arch| fat.exists(arch)) {
+        Ok(0)
+    } else {
+        Ok(1)
+    }
+}
+
+/// Run the `fat-macho` command line interface, returns the exit status
+///
+/// `argv` excludes the program name and defaults to `sys.argv[1:]`.
+#[pyfunction]
+#[pyo3(signature = (argv = None))]
+pub fn main(py: Python, argv: Option<Vec<String>>) -> PyResult<i32> {
+    let argv = match argv {
+        Some(argv) => argv,
+        None => {
+            let argv: Vec<String> = py.import("sys")?.getattr("argv")?.extract()?;
+            argv.into_iter().skip(1).collect()
+        }
+    };
+    let (command, rest) = match argv.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => {
+            write_stream(py, "stderr", USAGE)?;
+            return Ok(2);
+        }
+    };
+    if matches!(command, "-h" | "--help" | "help") {
+        write_stream(py, "stdout", USAGE)?;
+        return Ok(0);
+    }
+    let result = match Args::parse(rest) {
+        Ok(args) => match command {
+            "create" => create(&args),
+            "extract" => extract(&args),
+            "thin" => thin(&args),
+            "remove" => remove(&args),
+            "info" => info(py, &args)?,
+            "verify" => verify(&args),
+            _ => Err(Failure::Usage(format!("unknown command {}", command))),
+        },
+        Err(e) => Err(Failure::Usage(e)),
+    };
+    match result {
+        Ok(status) => Ok(status),
+        Err(Failure::Usage(msg)) => {
+            write_stream(
+                py,
+                "stderr",
+                &format!("fat-macho: error: {}\n{}", msg, USAGE),
+            )?;
+            Ok(2)
+        }
+        Err(Failure::Error(msg)) => {
+            write_stream(py, "stderr", &format!("fat-macho: error: {}\n", msg))?;
+            Ok(1)
+        }
+    }
+}
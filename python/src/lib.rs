@@ -1,30 +1,128 @@
-use pyo3::exceptions::{PyOSError, PyValueError};
+use std::fs;
+use std::io::{self, Write};
+use std::os::raw::c_char;
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyKeyError, PyOSError, PyTypeError, PyValueError};
+use pyo3::ffi;
 use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyIterator, PyList, PyMemoryView, PySlice};
+
+mod cli;
+
+// Not exposed by pyo3 under the limited API
+const PYBUF_READ: i32 = 0x100;
+const PYBUF_WRITE: i32 = 0x200;
+
+const COPY_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+create_exception!(
+    fat_macho,
+    FatMachoError,
+    PyValueError,
+    "Base class of all fat-macho errors"
+);
+create_exception!(
+    fat_macho,
+    NotFatBinaryError,
+    FatMachoError,
+    "Input is not a Mach-O fat binary"
+);
+create_exception!(
+    fat_macho,
+    DuplicatedArchError,
+    FatMachoError,
+    "Architecture already exists in the fat binary"
+);
+create_exception!(
+    fat_macho,
+    InvalidMachOError,
+    FatMachoError,
+    "Input is not a valid Mach-O file"
+);
+create_exception!(
+    fat_macho,
+    BitcodeError,
+    FatMachoError,
+    "Input is not valid LLVM bitcode"
+);
+create_exception!(
+    fat_macho,
+    IncompatiblePlatformError,
+    FatMachoError,
+    "Slice was built for a platform that conflicts with an existing slice"
+);
 
 struct ErrorWrapper(fat_macho_rs::Error);
 
 /// Mach-O fat binary writer
+///
+/// When `path` is given, the writer can be used as a context manager which
+/// writes the fat binary to `path` on a successful exit.
 #[pyclass(module = "fat_macho")]
 struct FatWriter {
     inner: fat_macho_rs::FatWriter,
+    path: Option<PathBuf>,
 }
 
 #[pymethods]
 impl FatWriter {
     /// Create a new Mach-O fat binary writer
     #[new]
-    fn new() -> Self {
-        Self {
-            inner: fat_macho_rs::FatWriter::new(),
-        }
+    #[pyo3(signature = (path = None, *, fat64 = false, mixed_platforms = false))]
+    fn new(path: Option<PathBuf>, fat64: bool, mixed_platforms: bool) -> Self {
+        let mut inner = fat_macho_rs::FatWriter::new();
+        inner.set_fat64(fat64);
+        inner.set_mixed_platforms(mixed_platforms);
+        Self { inner, path }
     }
 
     /// Add a new thin Mach-O binary
-    fn add(&mut self, data: Vec<u8>) -> PyResult<()> {
-        self.inner.add(data).map_err(ErrorWrapper)?;
+    ///
+    /// `data` can be any object supporting the buffer protocol
+    /// (`bytes`, `bytearray`, `memoryview`, `mmap`...) or an `os.PathLike` path.
+    fn add(&mut self, py: Python, data: &Bound<'_, PyAny>) -> PyResult<()> {
+        let data = read_input(py, data)?;
+        let inner = &mut self.inner;
+        py.detach(|| inner.add(data)).map_err(ErrorWrapper)?;
+        Ok(())
+    }
+
+    /// Add a new thin Mach-O binary from a file
+    fn add_file(&mut self, py: Python, path: PathBuf) -> PyResult<()> {
+        let inner = &mut self.inner;
+        py.detach(|| inner.add_file(path)).map_err(ErrorWrapper)?;
+        Ok(())
+    }
+
+    /// Add a slice of any format without looking at its contents
+    ///
+    /// `align` is in bytes and must be a power of two.
+    fn add_raw(
+        &mut self,
+        py: Python,
+        arch: &str,
+        align: u32,
+        data: &Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        let arch = arch.parse::<fat_macho_rs::Arch>().map_err(ErrorWrapper)?;
+        let data = read_input(py, data)?;
+        self.inner
+            .add_raw(arch, align, data)
+            .map_err(ErrorWrapper)?;
         Ok(())
     }
 
+    /// Add a thin Mach-O binary, replacing the existing slice(s) of the same architecture
+    ///
+    /// Returns the replaced slices.
+    fn replace(&mut self, py: Python, data: &Bound<'_, PyAny>) -> PyResult<Vec<Vec<u8>>> {
+        let data = read_input(py, data)?;
+        let inner = &mut self.inner;
+        Ok(py.detach(|| inner.replace(data)).map_err(ErrorWrapper)?)
+    }
+
     /// Remove an architecture
     fn remove(&mut self, arch: &str) -> Option<Vec<u8>> {
         self.inner.remove(arch)
@@ -35,17 +133,235 @@ impl FatWriter {
         self.inner.exists(arch)
     }
 
+    /// Get the thin binary of an architecture
+    fn get<'py>(&self, py: Python<'py>, arch: &str) -> Option<Bound<'py, PyBytes>> {
+        self.inner.get(arch).map(|data| PyBytes::new(py, data))
+    }
+
+    /// Names of the architectures in this fat binary, in output order
+    #[getter]
+    fn arches(&self) -> Vec<&'static str> {
+        self.inner.arches()
+    }
+
+    /// Whether the fat64 format is forced
+    #[getter]
+    fn fat64(&self) -> bool {
+        self.inner.is_fat64()
+    }
+
+    #[setter]
+    fn set_fat64(&mut self, fat64: bool) {
+        self.inner.set_fat64(fat64);
+    }
+
+    /// Whether slices built for incompatible platforms are allowed
+    #[getter]
+    fn mixed_platforms(&self) -> bool {
+        self.inner.allows_mixed_platforms()
+    }
+
+    #[setter]
+    fn set_mixed_platforms(&mut self, allow: bool) {
+        self.inner.set_mixed_platforms(allow);
+    }
+
+    /// Set the alignment of an architecture, must be a power of two
+    fn set_align(&mut self, arch: &str, align: u32) -> PyResult<()> {
+        if !self.inner.set_align(arch, align).map_err(ErrorWrapper)? {
+            return Err(PyKeyError::new_err(arch.to_string()));
+        }
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn __contains__(&self, arch: &str) -> bool {
+        self.inner.exists(arch)
+    }
+
+    /// Iterate over `(arch, data)` pairs, in output order
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        let slices = self
+            .inner
+            .iter()
+            .map(|(arch, data)| (arch, PyBytes::new(py, data)))
+            .collect::<Vec<_>>();
+        PyList::new(py, slices)?.try_iter()
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyResult<PyRef<'_, Self>> {
+        if slf.path.is_none() {
+            return Err(PyTypeError::new_err(
+                "FatWriter requires a path to be used as a context manager",
+            ));
+        }
+        Ok(slf)
+    }
+
+    fn __exit__(
+        &self,
+        py: Python,
+        exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        if exc_type.is_none() {
+            if let Some(path) = &self.path {
+                py.detach(|| self.inner.write_to_file(path))
+                    .map_err(ErrorWrapper)?;
+            }
+        }
+        Ok(false)
+    }
+
     /// Write Mach-O fat binary to a file
-    fn write_to(&self, path: &str) -> PyResult<()> {
-        self.inner.write_to_file(path).map_err(ErrorWrapper)?;
+    fn write_to(&self, py: Python, path: PathBuf) -> PyResult<()> {
+        py.detach(|| self.inner.write_to_file(path))
+            .map_err(ErrorWrapper)?;
         Ok(())
     }
 
+    /// Plan the layout of the fat binary without writing it
+    ///
+    /// Returns a dict with `fat64`, `header_size`, `total_size` and the `slices`
+    /// in file order, each a dict with `arch`, `offset`, `size`, `align` and `padding`.
+    fn plan<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let layout = self.inner.plan().map_err(ErrorWrapper)?;
+        let slices = PyList::empty(py);
+        for slice in &layout.slices {
+            let item = PyDict::new(py);
+            item.set_item("arch", slice.arch)?;
+            item.set_item("offset", slice.offset)?;
+            item.set_item("size", slice.size)?;
+            item.set_item("align", slice.align)?;
+            item.set_item("padding", slice.padding)?;
+            slices.append(item)?;
+        }
+        let dict = PyDict::new(py);
+        dict.set_item("fat64", layout.fat64)?;
+        dict.set_item("header_size", layout.header_size)?;
+        dict.set_item("total_size", layout.total_size)?;
+        dict.set_item("slices", slices)?;
+        Ok(dict)
+    }
+
     /// Generate Mach-O fat binary and return bytes
-    fn generate(&self) -> PyResult<Vec<u8>> {
-        let mut data = Vec::new();
-        self.inner.write_to(&mut data).map_err(ErrorWrapper)?;
-        Ok(data)
+    ///
+    /// If `file` is given, the fat binary is written directly into the
+    /// binary file object instead and `None` is returned.
+    #[pyo3(signature = (file = None))]
+    fn generate<'py>(
+        &self,
+        py: Python<'py>,
+        file: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        if let Some(file) = file {
+            let mut writer = PyFileWriter { file };
+            self.inner.write_to(&mut writer).map_err(ErrorWrapper)?;
+            return Ok(None);
+        }
+        // The planned size lets the fat binary be written straight into the
+        // `bytes` object without an intermediate buffer
+        let layout = py.detach(|| self.inner.plan()).map_err(ErrorWrapper)?;
+        let bytes = PyBytes::new_with(py, layout.total_size as usize, |mut buf| {
+            py.detach(|| self.inner.write_to(&mut buf))
+                .map_err(ErrorWrapper)?;
+            Ok(())
+        })?;
+        Ok(Some(bytes))
+    }
+}
+
+/// Read input data from a buffer-protocol object or an `os.PathLike` path
+///
+/// The writer owns its slices, so buffer-protocol objects are copied once.
+fn read_input(py: Python, data: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    if let Ok(bytes) = data.cast::<PyBytes>() {
+        // `bytes` objects are immutable, so they can be copied without the GIL
+        let bytes = bytes.as_bytes();
+        return Ok(py.detach(|| bytes.to_vec()));
+    }
+    if let Ok(view) = PyMemoryView::from(data) {
+        if !view.getattr("c_contiguous")?.is_truthy()? {
+            return Err(PyTypeError::new_err("expected a C-contiguous buffer"));
+        }
+        // The buffer API isn't part of the limited API before Python 3.11, so
+        // let memoryview slice assignments copy the data straight into our Vec
+        let src = view.call_method1("cast", ("B",))?;
+        let len = src.len()?;
+        let mut buf = vec![0u8; len];
+        let dst = memoryview_from_raw(py, buf.as_mut_ptr(), len, PYBUF_WRITE)?;
+        let copied = copy_in_chunks(py, &src, &dst, len);
+        dst.call_method0("release")?;
+        copied?;
+        return Ok(buf);
+    }
+    if let Ok(path) = data.extract::<PathBuf>() {
+        return py.detach(|| fs::read(path)).map_err(io_error);
+    }
+    Err(PyTypeError::new_err(format!(
+        "expected a bytes-like object or os.PathLike, got {}",
+        data.get_type().name()?
+    )))
+}
+
+/// Copy between two memoryviews, letting other threads run between chunks
+///
+/// Slice assignments need the GIL, so large buffers are copied in chunks.
+fn copy_in_chunks(
+    py: Python<'_>,
+    src: &Bound<'_, PyAny>,
+    dst: &Bound<'_, PyAny>,
+    len: usize,
+) -> PyResult<()> {
+    for start in (0..len).step_by(COPY_CHUNK_SIZE) {
+        let end = len.min(start + COPY_CHUNK_SIZE);
+        let chunk = PySlice::new(py, start as isize, end as isize, 1);
+        dst.set_item(&chunk, src.get_item(&chunk)?)?;
+        py.detach(|| ());
+    }
+    Ok(())
+}
+
+/// Wrap raw memory in a memoryview, the caller must release it before the memory goes away
+fn memoryview_from_raw(
+    py: Python<'_>,
+    ptr: *mut u8,
+    len: usize,
+    flags: i32,
+) -> PyResult<Bound<'_, PyAny>> {
+    unsafe {
+        let view = ffi::PyMemoryView_FromMemory(ptr as *mut c_char, len as ffi::Py_ssize_t, flags);
+        Bound::from_owned_ptr_or_err(py, view)
+    }
+}
+
+/// `io::Write` adapter over a Python binary file object
+struct PyFileWriter<'a, 'py> {
+    file: &'a Bound<'py, PyAny>,
+}
+
+impl Write for PyFileWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let py = self.file.py();
+        // Expose `buf` as a read-only memoryview to avoid copying it into a `bytes`
+        let view = memoryview_from_raw(py, buf.as_ptr() as *mut u8, buf.len(), PYBUF_READ)?;
+        let written = self.file.call_method1("write", (&view,));
+        // The memoryview must not outlive `buf`
+        view.call_method0("release")?;
+        match written?.extract::<Option<usize>>()? {
+            Some(n) => Ok(n),
+            // Unbuffered raw files may return `None` when no data could be written
+            None => Ok(0),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.call_method0("flush")?;
+        Ok(())
     }
 }
 
@@ -53,22 +369,80 @@ impl From<ErrorWrapper> for PyErr {
     fn from(err: ErrorWrapper) -> Self {
         use fat_macho_rs::Error;
 
+        let message = err.0.to_string();
         match err.0 {
-            Error::Io(e) => PyOSError::new_err(e.to_string()),
-            Error::Bitcode(e) => PyValueError::new_err(e.to_string()),
-            Error::InvalidMachO(e) => PyValueError::new_err(e.to_string()),
-            Error::DuplicatedArch(e) => PyValueError::new_err(e.to_string()),
-            Error::Goblin(e) => PyValueError::new_err(e.to_string()),
-            Error::NotFatBinary => {
-                PyValueError::new_err("input is not a Mach-O fat binary".to_string())
+            Error::Io(e) => io_error(e),
+            Error::Bitcode(_) => BitcodeError::new_err(message),
+            Error::InvalidMachO(_)
+            | Error::Goblin(_)
+            | Error::ArchMismatch { .. }
+            | Error::UuidMismatch { .. } => InvalidMachOError::new_err(message),
+            Error::DuplicatedArch(arch) => {
+                with_attrs(DuplicatedArchError::new_err(message), &[("arch", arch)])
             }
+            Error::IncompatiblePlatform {
+                arch,
+                platform,
+                existing_arch,
+                existing_platform,
+            } => with_attrs(
+                IncompatiblePlatformError::new_err(message),
+                &[
+                    ("arch", arch),
+                    ("platform", platform.to_string()),
+                    ("existing_arch", existing_arch),
+                    ("existing_platform", existing_platform.to_string()),
+                ],
+            ),
+            Error::NotFatBinary => NotFatBinaryError::new_err(message),
+            Error::UnsupportedTriple(_) | Error::InvalidAlignment { .. } => {
+                FatMachoError::new_err(message)
+            }
+            // Variants added in later versions of the crate
+            _ => FatMachoError::new_err(message),
+        }
+    }
+}
+
+/// Convert an I/O error into the `OSError` subclass Python picks for its errno
+fn io_error(err: std::io::Error) -> PyErr {
+    match err.raw_os_error() {
+        Some(errno) => {
+            let message = err.to_string();
+            let strerror = message
+                .strip_suffix(&format!(" (os error {})", errno))
+                .unwrap_or(&message);
+            PyOSError::new_err((errno, strerror.to_string()))
         }
+        None => PyErr::from(err),
     }
 }
 
+/// Set string attributes on the exception value
+fn with_attrs(err: PyErr, attrs: &[(&str, String)]) -> PyErr {
+    Python::attach(|py| {
+        for (name, value) in attrs {
+            if let Err(e) = err.value(py).setattr(*name, value) {
+                return e;
+            }
+        }
+        err
+    })
+}
+
 #[pymodule]
-fn fat_macho(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+fn _fat_macho(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("__version__", env!("CARGO_PKG_VERSION"))?;
     m.add_class::<FatWriter>()?;
+    m.add_function(wrap_pyfunction!(cli::main, m)?)?;
+    m.add("FatMachoError", py.get_type::<FatMachoError>())?;
+    m.add("NotFatBinaryError", py.get_type::<NotFatBinaryError>())?;
+    m.add("DuplicatedArchError", py.get_type::<DuplicatedArchError>())?;
+    m.add("InvalidMachOError", py.get_type::<InvalidMachOError>())?;
+    m.add("BitcodeError", py.get_type::<BitcodeError>())?;
+    m.add(
+        "IncompatiblePlatformError",
+        py.get_type::<IncompatiblePlatformError>(),
+    )?;
     Ok(())
 }
@@ -0,0 +1,135 @@
+import os
+import stat
+import subprocess
+import sys
+from pathlib import Path
+
+import pytest
+
+from fat_macho import FatWriter, main
+
+FIXTURES = Path(__file__).resolve().parents[2] / "tests" / "fixtures"
+THIN_X86_64 = str(FIXTURES / "thin_x86_64")
+THIN_ARM64 = str(FIXTURES / "thin_arm64")
+
+
+@pytest.fixture
+def universal(tmp_path):
+    output = tmp_path / "universal"
+    assert main(["create", "-o", str(output), THIN_X86_64, THIN_ARM64]) == 0
+    return str(output)
+
+
+def arches(path):
+    writer = FatWriter()
+    writer.add(path)
+    return writer.arches
+
+
+def test_create(universal):
+    assert arches(universal) == ["x86_64", "arm64"]
+
+
+def test_create_fat64(tmp_path):
+    output = tmp_path / "universal"
+    assert main(["create", "--fat64", "--output", str(output), THIN_X86_64]) == 0
+    assert output.read_bytes()[:4] == b"\xca\xfe\xba\xbf"
+
+
+def test_extract(tmp_path, universal):
+    output = tmp_path / "extracted"
+    assert main(["extract", "-o", str(output), universal, "arm64"]) == 0
+    assert arches(output) == ["arm64"]
+
+
+def test_thin(tmp_path, universal):
+    output = tmp_path / "thin"
+    assert main(["thin", "-o", str(output), universal, "x86_64"]) == 0
+    assert output.read_bytes() == Path(THIN_X86_64).read_bytes()
+
+
+@pytest.mark.skipif(os.name != "posix", reason="requires POSIX permissions")
+def test_thin_keeps_permissions(tmp_path, universal):
+    os.chmod(universal, 0o755)
+    output = tmp_path / "thin"
+    assert main(["thin", "-o", str(output), universal, "arm64"]) == 0
+    assert stat.S_IMODE(output.stat().st_mode) == 0o755
+
+
+def test_remove(tmp_path, universal):
+    output = tmp_path / "removed"
+    assert main(["remove", "-o", str(output), universal, "x86_64"]) == 0
+    assert arches(output) == ["arm64"]
+    assert main(["remove", "-o", str(output), universal, "x86_64", "arm64"]) == 1
+
+
+def test_info(capsys, universal):
+    assert main(["info", universal, THIN_ARM64]) == 0
+    out = capsys.readouterr().out
+    assert f"Fat binary {universal}: 2 architectures" in out
+    assert f"Non-fat file {THIN_ARM64}: architecture arm64" in out
+
+
+def test_info_arm64e(capsys, tmp_path):
+    # arm64e with a versioned pointer authentication ABI in the cpusubtype
+    data = bytearray(Path(THIN_ARM64).read_bytes())
+    data[8:12] = (0x81000002).to_bytes(4, "little")
+    output = tmp_path / "universal"
+    writer = FatWriter()
+    writer.add(THIN_X86_64)
+    writer.add(data)
+    writer.write_to(output)
+    assert main(["info", str(output)]) == 0
+    out = capsys.readouterr().out
+    assert "  arm64e " in out
+    assert "unknown" not in out
+
+
+def test_verify(universal):
+    assert main(["verify", universal, "x86_64", "arm64"]) == 0
+    assert main(["verify", universal, "i386"]) == 1
+
+
+def test_help(capsys):
+    assert main(["--help"]) == 0
+    assert capsys.readouterr().out.startswith("usage: fat-macho")
+
+
+@pytest.mark.parametrize(
+    "argv, message",
+    [
+        ([], "usage: fat-macho"),
+        (["frobnicate"], "unknown command frobnicate"),
+        (["create", "--frobnicate", "-o", "out", THIN_X86_64], "unknown option --frobnicate"),
+        (["create", THIN_X86_64, "-o"], "-o requires an argument"),
+        (["create", THIN_X86_64], "missing -o OUTPUT"),
+        (["create", "-o", "out"], "missing INPUT"),
+        (["extract", "-o", "out", THIN_X86_64], "missing architecture"),
+        (["thin", "-o", "out", THIN_X86_64, "x86_64", "arm64"], "exactly one architecture"),
+        (["info"], "missing INPUT"),
+        (["verify"], "missing INPUT"),
+    ],
+)
+def test_usage_errors(capsys, argv, message):
+    assert main(argv) == 2
+    assert message in capsys.readouterr().err
+
+
+def test_errors(capsys, tmp_path):
+    output = str(tmp_path / "out")
+    assert main(["create", "-o", output, str(tmp_path / "missing")]) == 1
+    assert main(["create", "-o", output, THIN_X86_64, THIN_X86_64]) == 1
+    assert "duplicated architecture x86_64" in capsys.readouterr().err
+    assert main(["thin", "-o", output, THIN_X86_64, "arm64"]) == 1
+    assert "does not contain the arm64 architecture" in capsys.readouterr().err
+
+
+def test_module_entry_point(universal):
+    result = subprocess.run(
+        [sys.executable, "-m", "fat_macho", "verify", universal, "arm64"],
+        capture_output=True,
+    )
+    assert result.returncode == 0
+    result = subprocess.run([sys.executable, "-m", "fat_macho"], capture_output=True)
+    assert result.returncode == 2
+    assert result.stderr.startswith(b"usage: fat-macho")
@@ -0,0 +1,64 @@
+from pathlib import Path
+
+import pytest
+
+from fat_macho import (
+    DuplicatedArchError,
+    FatMachoError,
+    FatWriter,
+    IncompatiblePlatformError,
+    InvalidMachOError,
+    NotFatBinaryError,
+)
+
+FIXTURES = Path(__file__).resolve().parents[2] / "tests" / "fixtures"
+
+
+@pytest.mark.parametrize(
+    "exc",
+    [
+        DuplicatedArchError,
+        IncompatiblePlatformError,
+        InvalidMachOError,
+        NotFatBinaryError,
+    ],
+)
+def test_error_hierarchy(exc):
+    assert issubclass(exc, FatMachoError)
+    assert issubclass(exc, ValueError)
+
+
+def test_missing_file(tmp_path):
+    writer = FatWriter()
+    with pytest.raises(FileNotFoundError) as excinfo:
+        writer.add_file(tmp_path / "missing")
+    assert excinfo.value.errno is not None
+    with pytest.raises(FileNotFoundError) as excinfo:
+        writer.add(tmp_path / "missing")
+    assert excinfo.value.errno is not None
+
+
+def test_duplicated_arch():
+    writer = FatWriter()
+    writer.add_file(FIXTURES / "thin_x86_64")
+    with pytest.raises(DuplicatedArchError) as excinfo:
+        writer.add_file(FIXTURES / "thin_x86_64")
+    assert excinfo.value.arch == "x86_64"
+
+
+def test_incompatible_platform():
+    writer = FatWriter()
+    writer.add_file(FIXTURES / "thin_arm64_ios")
+    with pytest.raises(IncompatiblePlatformError) as excinfo:
+        writer.add_file(FIXTURES / "thin_arm64_iossim")
+    assert excinfo.value.arch == "arm64"
+    assert excinfo.value.existing_arch == "arm64"
+    assert excinfo.value.platform != excinfo.value.existing_platform
+
+
+def test_invalid_macho():
+    writer = FatWriter()
+    with pytest.raises(InvalidMachOError):
+        writer.add(b"\xca\xfe\xba\xbe" + b"\x00" * 4)
+    with pytest.raises(FatMachoError):
+        writer.add(b"not a mach-o file")
@@ -0,0 +1,90 @@
+import mmap
+from pathlib import Path
+
+import pytest
+
+from fat_macho import FatWriter
+
+FIXTURES = Path(__file__).resolve().parents[2] / "tests" / "fixtures"
+THIN_X86_64 = FIXTURES / "thin_x86_64"
+
+
+class PathLike:
+    def __init__(self, path):
+        self.path = path
+
+    def __fspath__(self):
+        return str(self.path)
+
+
+def expected():
+    writer = FatWriter()
+    writer.add_file(THIN_X86_64)
+    return writer.generate()
+
+
+@pytest.mark.parametrize(
+    "wrap",
+    [
+        bytes,
+        bytearray,
+        memoryview,
+        lambda data: memoryview(bytearray(data)),
+        # Multi-byte items are read as raw bytes
+        lambda data: memoryview(data).cast("I"),
+    ],
+)
+def test_buffer_inputs(wrap):
+    data = THIN_X86_64.read_bytes()
+    assert len(data) % 4 == 0
+    writer = FatWriter()
+    writer.add(wrap(data))
+    assert writer.get("x86_64") == data
+    assert writer.generate() == expected()
+
+
+def test_mmap_input():
+    with open(THIN_X86_64, "rb") as f:
+        with mmap.mmap(f.fileno(), 0, access=mmap.ACCESS_READ) as mm:
+            writer = FatWriter()
+            writer.add(mm)
+    assert writer.generate() == expected()
+
+
+@pytest.mark.parametrize("wrap", [Path, str, PathLike])
+def test_path_inputs(wrap):
+    writer = FatWriter()
+    writer.add(wrap(THIN_X86_64))
+    assert writer.generate() == expected()
+
+
+def test_input_is_copied():
+    data = bytearray(THIN_X86_64.read_bytes())
+    writer = FatWriter()
+    writer.add(data)
+    data[:] = b"\x00" * len(data)
+    assert writer.generate() == expected()
+
+
+def test_non_contiguous_buffer():
+    data = THIN_X86_64.read_bytes()
+    writer = FatWriter()
+    with pytest.raises(TypeError, match="contiguous"):
+        writer.add(memoryview(data + data)[::2])
+    assert len(writer) == 0
+
+
+@pytest.mark.parametrize("value", [None, 42, ["x86_64"]])
+def test_unsupported_inputs(value):
+    writer = FatWriter()
+    with pytest.raises(TypeError):
+        writer.add(value)
+
+
+def test_generate_into_file(tmp_path):
+    writer = FatWriter()
+    writer.add_file(THIN_X86_64)
+    output = tmp_path / "out"
+    with open(output, "wb") as f:
+        assert writer.generate(f) is None
+    assert output.read_bytes() == expected()
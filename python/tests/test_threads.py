@@ -0,0 +1,59 @@
+import contextlib
+import os
+import subprocess
+import sys
+import threading
+from pathlib import Path
+
+import pytest
+
+from fat_macho import FatWriter
+
+FIXTURES = Path(__file__).resolve().parents[2] / "tests" / "fixtures"
+
+# Writes a file into a named pipe once the test thread gives the go-ahead, or
+# after a timeout so that a test holding the GIL fails instead of hanging
+FEEDER = """
+import select
+import sys
+
+with open(sys.argv[1], "wb") as fifo:
+    print("opened", flush=True)
+    ready, _, _ = select.select([sys.stdin], [], [], 10)
+    with open(sys.argv[2], "rb") as f:
+        fifo.write(f.read())
+sys.exit(0 if ready else 1)
+"""
+
+
+@pytest.mark.skipif(not hasattr(os, "mkfifo"), reason="requires named pipes")
+def test_add_releases_gil(tmp_path):
+    fifo = tmp_path / "thin_x86_64"
+    os.mkfifo(fifo)
+    feeder = subprocess.Popen(
+        [sys.executable, "-c", FEEDER, str(fifo), str(FIXTURES / "thin_x86_64")],
+        stdin=subprocess.PIPE,
+        stdout=subprocess.PIPE,
+    )
+    writer = FatWriter()
+    worker = threading.Thread(target=writer.add, args=(fifo,))
+    worker.start()
+
+    # The feeder only opens the pipe once `add` is blocked reading it, and `add`
+    # only returns after the go-ahead which this thread can't give while `add`
+    # holds the GIL
+    assert feeder.stdout.readline() == b"opened\n"
+    with contextlib.suppress(BrokenPipeError):
+        feeder.stdin.write(b"go\n")
+        feeder.stdin.close()
+    worker.join()
+
+    assert feeder.wait() == 0
+    assert writer.arches == ["x86_64"]
+
+
+def test_add_buffer_copies_in_chunks():
+    data = bytearray((FIXTURES / "thin_x86_64").read_bytes() + bytes(20 * 1024 * 1024))
+    writer = FatWriter()
+    writer.add(memoryview(data))
+    assert writer.get("x86_64") == data
@@ -0,0 +1,141 @@
+from pathlib import Path
+
+import pytest
+
+from fat_macho import (
+    DuplicatedArchError,
+    FatMachoError,
+    FatWriter,
+    IncompatiblePlatformError,
+)
+
+FIXTURES = Path(__file__).resolve().parents[2] / "tests" / "fixtures"
+THIN_X86_64 = (FIXTURES / "thin_x86_64").read_bytes()
+THIN_ARM64 = (FIXTURES / "thin_arm64").read_bytes()
+
+
+@pytest.fixture
+def writer():
+    writer = FatWriter()
+    writer.add(THIN_X86_64)
+    writer.add(THIN_ARM64)
+    return writer
+
+
+def test_arches(writer):
+    assert writer.arches == ["x86_64", "arm64"]
+    assert len(writer) == 2
+    assert list(writer) == [("x86_64", THIN_X86_64), ("arm64", THIN_ARM64)]
+    assert writer.exists("arm64")
+    assert "x86_64" in writer
+    assert not writer.exists("armv7")
+    assert "armv7" not in writer
+
+
+def test_get(writer):
+    assert writer.get("x86_64") == THIN_X86_64
+    assert writer.get("arm64") == THIN_ARM64
+    assert writer.get("armv7") is None
+
+
+def test_remove(writer):
+    assert writer.remove("x86_64") == THIN_X86_64
+    assert writer.arches == ["arm64"]
+    assert writer.remove("x86_64") is None
+    assert len(writer) == 1
+
+
+def test_replace(writer):
+    with pytest.raises(DuplicatedArchError):
+        writer.add(THIN_X86_64)
+    assert writer.replace(THIN_X86_64) == [THIN_X86_64]
+    assert writer.arches == ["x86_64", "arm64"]
+    writer.remove("arm64")
+    assert writer.replace(THIN_ARM64) == []
+    assert writer.arches == ["x86_64", "arm64"]
+
+
+def test_add_raw():
+    firmware = b"\x7fELF" + b"\x00" * 60
+    writer = FatWriter()
+    with pytest.raises(FatMachoError):
+        writer.add_raw("armv7", 3, firmware)
+    with pytest.raises(FatMachoError):
+        writer.add_raw("not-an-arch", 0x1000, firmware)
+    writer.add_raw("armv7", 0x1000, firmware)
+    assert writer.arches == ["armv7"]
+    assert writer.get("armv7") == firmware
+    assert writer.plan()["slices"][0]["offset"] == 0x1000
+
+
+def test_plan(writer):
+    plan = writer.plan()
+    assert not plan["fat64"]
+    assert plan["header_size"] == 8 + 2 * 20
+    assert [s["arch"] for s in plan["slices"]] == writer.arches
+    assert [s["size"] for s in plan["slices"]] == [len(THIN_X86_64), len(THIN_ARM64)]
+    for s in plan["slices"]:
+        assert s["offset"] % s["align"] == 0
+    last = plan["slices"][-1]
+    assert plan["total_size"] == last["offset"] + last["size"]
+    assert plan["total_size"] == len(writer.generate())
+    assert FatWriter().plan()["slices"] == []
+
+
+def test_set_align(writer):
+    writer.set_align("arm64", 0x1000)
+    assert writer.plan()["slices"][1]["align"] == 0x1000
+    with pytest.raises(KeyError):
+        writer.set_align("armv7", 0x1000)
+    with pytest.raises(FatMachoError):
+        writer.set_align("arm64", 3)
+
+
+def test_fat64(writer):
+    assert not writer.fat64
+    writer.fat64 = True
+    data = writer.generate()
+    assert data[:4] == b"\xca\xfe\xba\xbf"
+    assert writer.plan()["header_size"] == 8 + 2 * 32
+    assert FatWriter(fat64=True).fat64
+
+
+def test_mixed_platforms():
+    ios = FIXTURES / "thin_arm64_ios"
+    writer = FatWriter()
+    writer.add_file(ios)
+    with pytest.raises(IncompatiblePlatformError):
+        writer.add(THIN_X86_64)
+    writer.mixed_platforms = True
+    writer.add(THIN_X86_64)
+    assert writer.arches == ["x86_64", "arm64"]
+    assert FatWriter(mixed_platforms=True).mixed_platforms
+
+
+def test_context_manager(tmp_path):
+    output = tmp_path / "fat"
+    with FatWriter(output) as writer:
+        writer.add(THIN_X86_64)
+        writer.add(THIN_ARM64)
+    assert output.read_bytes() == writer.generate()
+
+
+def test_context_manager_error(tmp_path):
+    output = tmp_path / "fat"
+    with pytest.raises(RuntimeError):
+        with FatWriter(output) as writer:
+            writer.add(THIN_X86_64)
+            raise RuntimeError("boom")
+    assert not output.exists()
+
+
+def test_context_manager_requires_path():
+    with pytest.raises(TypeError):
+        with FatWriter():
+            pass
+
+
+def test_write_to(writer, tmp_path):
+    output = tmp_path / "fat"
+    writer.write_to(output)
+    assert output.read_bytes() == writer.generate()
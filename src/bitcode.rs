@@ -0,0 +1,221 @@
+//! LLVM bitcode slices, optionally wrapped in the Darwin bitcode wrapper header
+use goblin::mach::cputype::{
+    CpuSubType, CpuType, CPU_SUBTYPE_ARM64_32_ALL, CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_ARM_V7,
+    CPU_SUBTYPE_I386_ALL, CPU_SUBTYPE_POWERPC_ALL, CPU_SUBTYPE_X86_64_ALL, CPU_TYPE_ANY,
+    CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_I386, CPU_TYPE_POWERPC,
+    CPU_TYPE_POWERPC64, CPU_TYPE_X86_64,
+};
+use llvm_bitcode::{bitcode::BitcodeElement, Bitcode};
+
+use crate::{
+    error::Error,
+    triple::get_arch_from_triple,
+    write::{LLVM_BITCODE_MAGIC, LLVM_BITCODE_WRAPPER_MAGIC},
+};
+
+/// Header of the Darwin LLVM bitcode wrapper
+///
+/// All fields are little-endian, the bitcode itself lives at `offset..offset + size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitcodeWrapper {
+    pub version: u32,
+    pub offset: u32,
+    pub size: u32,
+    /// Mach-O cputype, `0` or `CPU_TYPE_ANY` when unknown
+    pub cputype: u32,
+}
+
+impl BitcodeWrapper {
+    /// Size of the wrapper header in bytes
+    pub const SIZE: usize = 20;
+
+    /// Parse the wrapper header, checking the bitcode range against the buffer
+    pub fn parse(buffer: &[u8]) -> Result<Self, Error> {
+        if buffer.len() < Self::SIZE {
+            return Err(Error::InvalidMachO(
+                "bitcode wrapper header is truncated".to_string(),
+            ));
+        }
+        let field =
+            |index: usize| u32::from_le_bytes(buffer[index * 4..index * 4 + 4].try_into().unwrap());
+        if field(0) != LLVM_BITCODE_WRAPPER_MAGIC {
+            return Err(Error::InvalidMachO(
+                "input is not a bitcode wrapper".to_string(),
+            ));
+        }
+        let wrapper = Self {
+            version: field(1),
+            offset: field(2),
+            size: field(3),
+            cputype: field(4),
+        };
+        let end = wrapper.offset as u64 + wrapper.size as u64;
+        if (wrapper.offset as usize) < Self::SIZE || end > buffer.len() as u64 {
+            return Err(Error::InvalidMachO(format!(
+                "bitcode range {}..{} is out of bounds of the {} bytes wrapper",
+                wrapper.offset,
+                end,
+                buffer.len()
+            )));
+        }
+        Ok(wrapper)
+    }
+
+    /// The wrapped bitcode
+    pub fn bitcode<'a>(&self, buffer: &'a [u8]) -> &'a [u8] {
+        &buffer[self.offset as usize..(self.offset + self.size) as usize]
+    }
+
+    /// The cputype stored in the header, if any
+    pub fn cpu_type(&self) -> Option<CpuType> {
+        match self.cputype {
+            0 | CPU_TYPE_ANY => None,
+            cputype => Some(cputype),
+        }
+    }
+}
+
+/// Whether the buffer is LLVM bitcode without the wrapper header
+pub(crate) fn is_raw_bitcode(buffer: &[u8]) -> bool {
+    buffer.starts_with(&LLVM_BITCODE_MAGIC.to_le_bytes())
+}
+
+/// Wrap raw bitcode in the Darwin wrapper header, padding it to a multiple of 16 bytes
+pub(crate) fn wrap_bitcode(bitcode: &[u8], cpu_type: CpuType) -> Vec<u8> {
+    let size = BitcodeWrapper::SIZE + bitcode.len();
+    let mut buffer = Vec::with_capacity((size + 15) & !15);
+    for field in [
+        LLVM_BITCODE_WRAPPER_MAGIC,
+        0,
+        BitcodeWrapper::SIZE as u32,
+        bitcode.len() as u32,
+        cpu_type,
+    ] {
+        buffer.extend_from_slice(&field.to_le_bytes());
+    }
+    buffer.extend_from_slice(bitcode);
+    buffer.resize((size + 15) & !15, 0);
+    buffer
+}
+
+/// Determine the architecture of a raw or wrapped bitcode slice
+///
+/// The cputype of the wrapper header is preferred, the target triple of the module
+/// is only consulted when the header has no cputype or it doesn't imply a cpusubtype.
+pub(crate) fn get_arch_from_bitcode(buffer: &[u8]) -> Result<(CpuType, CpuSubType), Error> {
+    if is_raw_bitcode(buffer) {
+        return get_arch_from_bitcode_triple(buffer);
+    }
+    let wrapper = BitcodeWrapper::parse(buffer)?;
+    let bitcode = wrapper.bitcode(buffer);
+    let cpu_type = match wrapper.cpu_type() {
+        Some(cpu_type) => cpu_type,
+        None => return get_arch_from_bitcode_triple(bitcode),
+    };
+    match cpu_type {
+        // These have several subtypes commonly used in fat binaries
+        CPU_TYPE_ARM | CPU_TYPE_ARM64 | CPU_TYPE_X86_64 => {
+            match get_arch_from_bitcode_triple(bitcode) {
+                Ok(arch) if arch.0 == cpu_type => Ok(arch),
+                _ => default_subtype(cpu_type).map(|cpu_subtype| (cpu_type, cpu_subtype)),
+            }
+        }
+        _ => default_subtype(cpu_type).map(|cpu_subtype| (cpu_type, cpu_subtype)),
+    }
+}
+
+fn default_subtype(cpu_type: CpuType) -> Result<CpuSubType, Error> {
+    Ok(match cpu_type {
+        CPU_TYPE_I386 => CPU_SUBTYPE_I386_ALL,
+        CPU_TYPE_X86_64 => CPU_SUBTYPE_X86_64_ALL,
+        CPU_TYPE_POWERPC | CPU_TYPE_POWERPC64 => CPU_SUBTYPE_POWERPC_ALL,
+        CPU_TYPE_ARM => CPU_SUBTYPE_ARM_V7,
+        CPU_TYPE_ARM64 => CPU_SUBTYPE_ARM64_ALL,
+        CPU_TYPE_ARM64_32 => CPU_SUBTYPE_ARM64_32_ALL,
+        _ => {
+            return Err(Error::InvalidMachO(format!(
+                "unsupported bitcode wrapper cputype {:#x}",
+                cpu_type
+            )))
+        }
+    })
+}
+
+fn get_arch_from_bitcode_triple(buffer: &[u8]) -> Result<(CpuType, CpuSubType), Error> {
+    let bitcode = Bitcode::new(buffer)?;
+    let target_triple = bitcode
+        .elements
+        .iter()
+        .find(|ele| match ele {
+            BitcodeElement::Record(_) => false,
+            BitcodeElement::Block(block) => block.id == 8,
+        })
+        .and_then(|module_block| {
+            module_block
+                .as_block()
+                .unwrap()
+                .elements
+                .iter()
+                .find(|ele| match ele {
+                    BitcodeElement::Record(record) => record.id == 2,
+                    BitcodeElement::Block(_) => false,
+                })
+        })
+        .and_then(|target_triple_record| {
+            let record = target_triple_record.as_record().unwrap();
+            let fields: Vec<u8> = record.fields.iter().map(|x| *x as u8).collect();
+            String::from_utf8(fields).ok()
+        });
+    match target_triple {
+        Some(triple) => get_arch_from_triple(&triple),
+        None => Err(Error::InvalidMachO(
+            "bitcode has no target triple".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use goblin::mach::cputype::{
+        CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_X86_64_ALL, CPU_TYPE_ARM64, CPU_TYPE_X86_64,
+    };
+
+    use super::{get_arch_from_bitcode, BitcodeWrapper};
+
+    #[test]
+    fn test_bitcode_wrapper_parse() {
+        let buf = fs::read("tests/fixtures/thin_x86_64.bc").unwrap();
+        let wrapper = BitcodeWrapper::parse(&buf).unwrap();
+        assert_eq!(wrapper.offset, 20);
+        assert_eq!(wrapper.cpu_type(), Some(CPU_TYPE_X86_64));
+        assert_eq!(&wrapper.bitcode(&buf)[..4], b"BC\xc0\xde");
+        assert_eq!(
+            get_arch_from_bitcode(&buf).unwrap(),
+            (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL)
+        );
+
+        // No cputype in the header, falls back to the target triple
+        let buf = fs::read("tests/fixtures/thin_arm64.bc").unwrap();
+        let wrapper = BitcodeWrapper::parse(&buf).unwrap();
+        assert_eq!(wrapper.cpu_type(), None);
+        assert_eq!(
+            get_arch_from_bitcode(&buf).unwrap(),
+            (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL)
+        );
+    }
+
+    #[test]
+    fn test_bitcode_wrapper_out_of_bounds() {
+        let buf = fs::read("tests/fixtures/thin_x86_64.bc").unwrap();
+        let wrapper = BitcodeWrapper::parse(&buf).unwrap();
+        let end = (wrapper.offset + wrapper.size) as usize;
+        assert!(BitcodeWrapper::parse(&buf[..end]).is_ok());
+        assert!(BitcodeWrapper::parse(&buf[..end - 1]).is_err());
+        assert!(BitcodeWrapper::parse(&buf[..12]).is_err());
+        let mut corrupted = buf.clone();
+        corrupted[8..12].copy_from_slice(&4u32.to_le_bytes());
+        assert!(BitcodeWrapper::parse(&corrupted).is_err());
+    }
+}
@@ -0,0 +1,813 @@
+//! Processing of every Mach-O file inside `.framework` and `.app` bundles
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use goblin::mach::{
+    fat::FAT_MAGIC,
+    header::{MH_CIGAM, MH_CIGAM_64, MH_MAGIC, MH_MAGIC_64},
+};
+
+use crate::{error::Error, manifest::FAT_MAGIC_64, read::FatReader, write::FatWriter};
+
+/// Kind of a Mach-O file detected by [`sniff`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachOKind {
+    Thin,
+    Fat,
+}
+
+/// Detect a Mach-O file from its first 8 bytes
+///
+/// Java class files share the fat magic number, they are told apart by their
+/// version number which is larger than any reasonable number of fat arches.
+pub fn sniff(header: &[u8]) -> Option<MachOKind> {
+    let magic = u32::from_be_bytes(header.get(..4)?.try_into().unwrap());
+    match magic {
+        MH_MAGIC | MH_CIGAM | MH_MAGIC_64 | MH_CIGAM_64 => Some(MachOKind::Thin),
+        FAT_MAGIC | FAT_MAGIC_64 => {
+            let nfat_arch = u32::from_be_bytes(header.get(4..8)?.try_into().unwrap());
+            (nfat_arch < 45).then_some(MachOKind::Fat)
+        }
+        _ => None,
+    }
+}
+
+/// A Mach-O file found in a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleFile {
+    /// Path relative to the bundle root
+    pub path: PathBuf,
+    pub kind: MachOKind,
+    pub arches: Vec<String>,
+}
+
+/// Operation applied to every Mach-O file of a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleOperation {
+    /// Keep only the listed architectures
+    Thin(Vec<String>),
+    /// Merge the file at the same path of another bundle, built for other architectures
+    Merge(PathBuf),
+    /// Remove the listed architectures
+    Remove(Vec<String>),
+}
+
+/// Outcome of a [`BundleOperation`] on one file
+#[derive(Debug)]
+pub struct ProcessedFile {
+    /// Path relative to the bundle root
+    pub path: PathBuf,
+    /// Architectures of the file afterwards
+    pub result: Result<Vec<String>, Error>,
+}
+
+/// A difference found by [`Bundle::merge`] between two bundles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleDifference {
+    /// Only present in the first bundle, children of a directory are not listed
+    OnlyInFirst(PathBuf),
+    /// Only present in the second bundle, children of a directory are not listed
+    OnlyInSecond(PathBuf),
+    /// Present in both with different contents, symbolic link targets or file types
+    Changed(PathBuf),
+}
+
+/// Outcome of [`Bundle::merge`]
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Mach-O files present in both bundles, the output holds the first bundle's
+    /// version of those that failed to merge
+    pub files: Vec<ProcessedFile>,
+    pub differences: Vec<BundleDifference>,
+}
+
+impl MergeReport {
+    /// Whether every Mach-O file was merged and the other files are identical
+    pub fn is_clean(&self) -> bool {
+        self.differences.is_empty() && self.files.iter().all(|file| file.result.is_ok())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Entry {
+    Dir,
+    File,
+    Symlink(PathBuf),
+}
+
+/// A `.framework`, `.app` or any other directory containing Mach-O files
+///
+/// Nested bundles are walked as well. Symbolic links are not followed, so the
+/// `Versions/Current` links of frameworks don't yield the same file twice.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    root: PathBuf,
+    overwrite: bool,
+}
+
+impl Bundle {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            overwrite: false,
+        }
+    }
+
+    /// Allow [`Bundle::merge`] to replace an existing non-empty output directory
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    /// Root directory of the bundle
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Find all thin and fat Mach-O files, in path order
+    pub fn files(&self) -> Result<Vec<BundleFile>, Error> {
+        let mut files = Vec::new();
+        for (path, kind) in self.walk()? {
+            let arches = arches(kind, fs::read(self.root.join(&path))?)?;
+            files.push(BundleFile { path, kind, arches });
+        }
+        Ok(files)
+    }
+
+    /// Apply an operation to every Mach-O file, rewriting the files in place
+    ///
+    /// A failure on one file doesn't stop the others from being processed,
+    /// files that fail are left untouched.
+    pub fn process(&self, operation: &BundleOperation) -> Result<Vec<ProcessedFile>, Error> {
+        Ok(self
+            .walk()?
+            .into_iter()
+            .map(|(path, _)| {
+                let result = self.process_file(&path, operation);
+                ProcessedFile { path, result }
+            })
+            .collect())
+    }
+
+    fn process_file(&self, path: &Path, operation: &BundleOperation) -> Result<Vec<String>, Error> {
+        let full_path = self.root.join(path);
+        let mut fat = FatWriter::new();
+        // Keep whatever the file already contains
+        fat.set_mixed_platforms(true);
+        fat.add(fs::read(&full_path)?)?;
+        let before = fat.len();
+        match operation {
+            BundleOperation::Thin(arches) => {
+                for arch in fat.arches() {
+                    if !arches.iter().any(|keep| keep == arch) {
+                        fat.remove(arch);
+                    }
+                }
+            }
+            BundleOperation::Merge(other_root) => {
+                fat.set_mixed_platforms(false);
+                fat.add(fs::read(other_root.join(path))?)?;
+            }
+            BundleOperation::Remove(arches) => {
+                for arch in arches {
+                    fat.remove(arch);
+                }
+            }
+        }
+        let arches: Vec<String> = fat.arches().into_iter().map(str::to_string).collect();
+        if fat.is_empty() {
+            return Err(Error::InvalidMachO(format!(
+                "no architectures left in {}",
+                path.display()
+            )));
+        }
+        if fat.len() != before || matches!(operation, BundleOperation::Merge(_)) {
+            match fat.iter().next() {
+                Some((_, data)) if fat.len() == 1 => replace_file(&full_path, data)?,
+                _ => {
+                    let mut out = Vec::new();
+                    fat.write_to(&mut out)?;
+                    replace_file(&full_path, &out)?;
+                }
+            }
+        }
+        Ok(arches)
+    }
+
+    /// Merge two bundles built for different architectures into a universal bundle
+    ///
+    /// Both bundles are walked in parallel: Mach-O files at the same path are combined
+    /// into fat binaries and all other files must be identical. Differences are reported
+    /// while the first bundle's version is written, files only present in one bundle
+    /// are copied as well. Symbolic links and permissions are kept.
+    ///
+    /// Mach-O files that can't be merged, for instance because both contain the same
+    /// architecture, are reported with an error in [`MergeReport::files`] and the first
+    /// bundle's version is written.
+    ///
+    /// `output` must not overlap either bundle. An existing non-empty `output` is only
+    /// replaced when enabled with [`Bundle::set_overwrite`].
+    pub fn merge<P: AsRef<Path>>(&self, other: &Bundle, output: P) -> Result<MergeReport, Error> {
+        let output = output.as_ref();
+        let first = entries(&self.root)?;
+        let second = entries(&other.root)?;
+        prepare_output_dir(output, &[&self.root, &other.root], self.overwrite)?;
+        fs::set_permissions(output, fs::metadata(&self.root)?.permissions())?;
+
+        let mut report = MergeReport::default();
+        let mut missing = BTreeSet::new();
+        let paths: BTreeSet<&PathBuf> = first.keys().chain(second.keys()).collect();
+        for path in paths {
+            let (root, entry) = match (first.get(path), second.get(path)) {
+                (Some(a), Some(b)) => {
+                    if let Some(file) =
+                        self.merge_entry(other, path, (a, b), output, &mut report)?
+                    {
+                        report.files.push(file);
+                    }
+                    // Children of a directory replaced by a file are not listed either
+                    if a != b {
+                        missing.insert(path.clone());
+                    }
+                    continue;
+                }
+                (Some(a), None) => {
+                    if !path.ancestors().any(|dir| missing.contains(dir)) {
+                        report
+                            .differences
+                            .push(BundleDifference::OnlyInFirst(path.clone()));
+                    }
+                    (&self.root, a)
+                }
+                (None, Some(b)) => {
+                    if !path.ancestors().any(|dir| missing.contains(dir)) {
+                        report
+                            .differences
+                            .push(BundleDifference::OnlyInSecond(path.clone()));
+                    }
+                    (&other.root, b)
+                }
+                (None, None) => unreachable!(),
+            };
+            missing.insert(path.clone());
+            let target = output.join(path);
+            // The parent was replaced by a file or symbolic link of the first bundle
+            if target
+                .parent()
+                .is_some_and(|parent| fs::symlink_metadata(parent).is_ok_and(|m| m.is_dir()))
+            {
+                copy_entry(&root.join(path), entry, &target)?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Merge an entry present in both bundles, returning the result for Mach-O files
+    fn merge_entry(
+        &self,
+        other: &Bundle,
+        path: &Path,
+        entries: (&Entry, &Entry),
+        output: &Path,
+        report: &mut MergeReport,
+    ) -> Result<Option<ProcessedFile>, Error> {
+        let source = self.root.join(path);
+        let target = output.join(path);
+        if entries.0 != entries.1 {
+            report
+                .differences
+                .push(BundleDifference::Changed(path.to_path_buf()));
+            copy_entry(&source, entries.0, &target)?;
+            return Ok(None);
+        }
+        if *entries.0 != Entry::File {
+            copy_entry(&source, entries.0, &target)?;
+            return Ok(None);
+        }
+        let first = fs::read(&source)?;
+        let second = fs::read(other.root.join(path))?;
+        if let (Some(kind), true) = (sniff(&first), first == second) {
+            // Already universal or shared by both builds
+            copy_entry(&source, entries.0, &target)?;
+            return Ok(Some(ProcessedFile {
+                path: path.to_path_buf(),
+                result: arches(kind, first),
+            }));
+        }
+        if sniff(&first).is_some() && sniff(&second).is_some() {
+            let result = (|| {
+                let mut fat = FatWriter::new();
+                fat.add(first)?;
+                fat.add(second)?;
+                fat.write_to_file(&target)?;
+                Ok(fat.arches().into_iter().map(str::to_string).collect())
+            })();
+            if result.is_err() {
+                copy_entry(&source, entries.0, &target)?;
+            } else {
+                fs::set_permissions(&target, fs::metadata(&source)?.permissions())?;
+            }
+            return Ok(Some(ProcessedFile {
+                path: path.to_path_buf(),
+                result,
+            }));
+        }
+        if first != second {
+            report
+                .differences
+                .push(BundleDifference::Changed(path.to_path_buf()));
+        }
+        copy_entry(&source, entries.0, &target)?;
+        Ok(None)
+    }
+
+    /// Relative paths of the Mach-O files, sorted
+    fn walk(&self) -> Result<Vec<(PathBuf, MachOKind)>, Error> {
+        let mut files = Vec::new();
+        let mut dirs = vec![PathBuf::new()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(self.root.join(&dir))? {
+                let entry = entry?;
+                let path = dir.join(entry.file_name());
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push(path);
+                } else if file_type.is_file() {
+                    let mut header = [0; 8];
+                    let mut file = File::open(entry.path())?;
+                    let len = read_header(&mut file, &mut header)?;
+                    if let Some(kind) = sniff(&header[..len]) {
+                        files.push((path, kind));
+                    }
+                }
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+}
+
+/// Replace a file through a temporary file in the same directory, keeping its permissions
+fn replace_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let permissions = fs::metadata(path)?.permissions();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let result = (|| {
+        let mut file = File::options().write(true).create_new(true).open(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::set_permissions(&temp, permissions)?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    Ok(result?)
+}
+
+/// Architectures of a thin or fat Mach-O file
+fn arches(kind: MachOKind, data: Vec<u8>) -> Result<Vec<String>, Error> {
+    Ok(match kind {
+        MachOKind::Thin => {
+            let mut fat = FatWriter::new();
+            fat.add(data)?;
+            fat.arches().into_iter().map(str::to_string).collect()
+        }
+        MachOKind::Fat => FatReader::new(&data)?
+            .slices()?
+            .into_iter()
+            .map(|slice| slice.arch)
+            .collect(),
+    })
+}
+
+/// All entries below `root` by relative path, without following symbolic links
+fn entries(root: &Path) -> Result<BTreeMap<PathBuf, Entry>, Error> {
+    let mut entries = BTreeMap::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                entries.insert(path, Entry::Symlink(fs::read_link(entry.path())?));
+            } else if file_type.is_dir() {
+                dirs.push(path.clone());
+                entries.insert(path, Entry::Dir);
+            } else {
+                entries.insert(path, Entry::File);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Copy a single entry, the parent directory must already exist
+fn copy_entry(source: &Path, entry: &Entry, target: &Path) -> Result<(), Error> {
+    match entry {
+        Entry::Dir => {
+            fs::create_dir(target)?;
+            fs::set_permissions(target, fs::metadata(source)?.permissions())?;
+        }
+        Entry::File => {
+            // Also copies the permissions
+            fs::copy(source, target)?;
+        }
+        Entry::Symlink(link) => symlink(link, target)?,
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(link: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(not(unix))]
+fn symlink(_link: &Path, target: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("can't create symbolic link {}", target.display()),
+    ))
+}
+
+/// Create an empty output directory
+///
+/// Fails if `output` is, contains or is inside one of the `inputs`, and if it
+/// already exists unless it is an empty directory or `replace` is set.
+pub(crate) fn prepare_output_dir(
+    output: &Path,
+    inputs: &[&Path],
+    replace: bool,
+) -> Result<(), Error> {
+    let absolute_output = absolute(output)?;
+    for input in inputs {
+        let input = absolute(input)?;
+        if absolute_output.starts_with(&input) || input.starts_with(&absolute_output) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "output {} overlaps the input {}",
+                    output.display(),
+                    input.display()
+                ),
+            )));
+        }
+    }
+    match fs::symlink_metadata(output) {
+        Ok(metadata) if metadata.is_dir() && fs::read_dir(output)?.next().is_none() => {}
+        Ok(metadata) if replace => {
+            if metadata.is_dir() {
+                fs::remove_dir_all(output)?;
+            } else {
+                fs::remove_file(output)?;
+            }
+        }
+        Ok(_) => {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", output.display()),
+            )))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    fs::create_dir_all(output)?;
+    Ok(())
+}
+
+/// Canonical form of a path that may not exist yet
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(canonical) => {
+                return Ok(rest
+                    .iter()
+                    .rev()
+                    .fold(canonical, |path, name| path.join(name)))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        rest.push(name);
+                        existing = if parent.as_os_str().is_empty() {
+                            Path::new(".")
+                        } else {
+                            parent
+                        };
+                    }
+                    _ => return Err(err),
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn read_header(file: &mut File, header: &mut [u8]) -> Result<usize, Error> {
+    let mut len = 0;
+    while len < header.len() {
+        match file.read(&mut header[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{sniff, Bundle, BundleDifference, BundleOperation, MachOKind};
+
+    fn create_bundle(root: &Path, inner: &str) {
+        if root.exists() {
+            fs::remove_dir_all(root).unwrap();
+        }
+        let resources = root.join("Contents/Resources");
+        fs::create_dir_all(&resources).unwrap();
+        fs::write(resources.join("Info.plist"), "<plist/>").unwrap();
+        let frameworks = root.join("Contents/Frameworks/Inner.framework");
+        fs::create_dir_all(&frameworks).unwrap();
+        fs::copy(
+            format!("tests/fixtures/{}", inner),
+            frameworks.join("Inner"),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("Inner", frameworks.join("Current")).unwrap();
+    }
+
+    #[test]
+    fn test_sniff() {
+        let buf = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert_eq!(sniff(&buf), Some(MachOKind::Thin));
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        assert_eq!(sniff(&buf), Some(MachOKind::Fat));
+        let buf = fs::read("tests/fixtures/thin_x86_64.a").unwrap();
+        assert_eq!(sniff(&buf), None);
+        // Java class file, version 52
+        assert_eq!(sniff(&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52]), None);
+    }
+
+    #[test]
+    fn test_bundle_process() {
+        let root = Path::new("tests/output/Hello.app");
+        create_bundle(root, "thin_x86_64");
+        let macos = root.join("Contents/MacOS");
+        fs::create_dir_all(&macos).unwrap();
+        fs::copy("tests/fixtures/simplefat", macos.join("Hello")).unwrap();
+
+        let bundle = Bundle::new(root);
+        let files = bundle.files().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0].path,
+            Path::new("Contents/Frameworks/Inner.framework/Inner")
+        );
+        assert_eq!(files[0].kind, MachOKind::Thin);
+        assert_eq!(files[1].kind, MachOKind::Fat);
+        assert_eq!(files[1].arches, vec!["x86_64", "arm64"]);
+
+        let results = bundle
+            .process(&BundleOperation::Thin(vec!["arm64".to_string()]))
+            .unwrap();
+        // The inner framework has no arm64 slice
+        assert!(results[0].result.is_err());
+        assert_eq!(results[1].result.as_ref().unwrap(), &vec!["arm64"]);
+        let files = bundle.files().unwrap();
+        assert_eq!(files[0].arches, vec!["x86_64"]);
+        assert_eq!(files[1].kind, MachOKind::Thin);
+    }
+
+    #[test]
+    fn test_bundle_merge() {
+        let x86_64 = Path::new("tests/output/Merge-x86_64.app");
+        let arm64 = Path::new("tests/output/Merge-arm64.app");
+        create_bundle(x86_64, "thin_x86_64");
+        create_bundle(arm64, "thin_arm64");
+
+        let bundle = Bundle::new(x86_64);
+        let results = bundle
+            .process(&BundleOperation::Merge(arm64.to_path_buf()))
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].result.as_ref().unwrap(),
+            &vec!["x86_64", "arm64"]
+        );
+        let files = bundle.files().unwrap();
+        assert_eq!(files[0].kind, MachOKind::Fat);
+
+        let results = bundle
+            .process(&BundleOperation::Remove(vec!["x86_64".to_string()]))
+            .unwrap();
+        assert_eq!(results[0].result.as_ref().unwrap(), &vec!["arm64"]);
+    }
+
+    #[test]
+    fn test_bundle_process_arm64e() {
+        use crate::write::FatWriter;
+
+        let root = Path::new("tests/output/Arm64e.app");
+        create_bundle(root, "thin_x86_64");
+        let arm64 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let mut arm64e = arm64.clone();
+        // arm64e with the versioned pointer authentication ABI 1
+        arm64e[8..12].copy_from_slice(&0x8100_0002u32.to_le_bytes());
+        let mut fat = FatWriter::new();
+        fat.add(arm64).unwrap();
+        fat.add(arm64e.clone()).unwrap();
+        let macos = root.join("Contents/MacOS");
+        fs::create_dir_all(&macos).unwrap();
+        fat.write_to_file(macos.join("Hello")).unwrap();
+
+        let bundle = Bundle::new(root);
+        let results = bundle
+            .process(&BundleOperation::Thin(vec!["arm64e".to_string()]))
+            .unwrap();
+        assert_eq!(results[1].path, Path::new("Contents/MacOS/Hello"));
+        assert_eq!(results[1].result.as_ref().unwrap(), &vec!["arm64e"]);
+        assert_eq!(fs::read(macos.join("Hello")).unwrap(), arm64e);
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(&macos).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(macos.join("Hello"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        fat.write_to_file(macos.join("Hello")).unwrap();
+        let results = bundle
+            .process(&BundleOperation::Remove(vec!["arm64e".to_string()]))
+            .unwrap();
+        assert_eq!(results[1].result.as_ref().unwrap(), &vec!["arm64"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_merge_bundles() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let x86_64 = Path::new("tests/output/Universal-x86_64.app");
+        let arm64 = Path::new("tests/output/Universal-arm64.app");
+        create_bundle(x86_64, "thin_x86_64");
+        create_bundle(arm64, "thin_arm64");
+        let resources = x86_64.join("Contents/Resources");
+        fs::write(resources.join("x86_64.txt"), "x86_64").unwrap();
+        fs::set_permissions(
+            resources.join("Info.plist"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        fs::write(arm64.join("Contents/Resources/Info.plist"), "<dict/>").unwrap();
+
+        let output = Path::new("tests/output/Universal.app");
+        let mut bundle = Bundle::new(x86_64);
+        bundle.set_overwrite(true);
+        let report = bundle.merge(&Bundle::new(arm64), output).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(
+            report.files[0].result.as_ref().unwrap(),
+            &vec!["x86_64", "arm64"]
+        );
+        assert_eq!(
+            report.differences,
+            vec![
+                BundleDifference::Changed("Contents/Resources/Info.plist".into()),
+                BundleDifference::OnlyInFirst("Contents/Resources/x86_64.txt".into()),
+            ]
+        );
+
+        let framework = output.join("Contents/Frameworks/Inner.framework");
+        assert_eq!(
+            fs::read_link(framework.join("Current")).unwrap(),
+            Path::new("Inner")
+        );
+        let files = Bundle::new(output).files().unwrap();
+        assert_eq!(files[0].kind, MachOKind::Fat);
+        let info_plist = output.join("Contents/Resources/Info.plist");
+        assert_eq!(fs::read_to_string(&info_plist).unwrap(), "<plist/>");
+        assert_eq!(
+            fs::metadata(&info_plist).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_merge_bundles_identical_macho() {
+        let x86_64 = Path::new("tests/output/Identical-x86_64.app");
+        let arm64 = Path::new("tests/output/Identical-arm64.app");
+        create_bundle(x86_64, "thin_x86_64");
+        create_bundle(arm64, "thin_arm64");
+        for root in [x86_64, arm64] {
+            let macos = root.join("Contents/MacOS");
+            fs::create_dir_all(&macos).unwrap();
+            fs::copy("tests/fixtures/simplefat", macos.join("Helper")).unwrap();
+        }
+
+        let output = Path::new("tests/output/Identical.app");
+        if output.exists() {
+            fs::remove_dir_all(output).unwrap();
+        }
+        let report = Bundle::new(x86_64)
+            .merge(&Bundle::new(arm64), output)
+            .unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.files[1].path, Path::new("Contents/MacOS/Helper"));
+        assert_eq!(
+            report.files[1].result.as_ref().unwrap(),
+            &vec!["x86_64", "arm64"]
+        );
+        assert_eq!(
+            fs::read(output.join("Contents/MacOS/Helper")).unwrap(),
+            fs::read("tests/fixtures/simplefat").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_bundles_file_replaced_by_dir() {
+        let x86_64 = Path::new("tests/output/Replaced-x86_64.app");
+        let arm64 = Path::new("tests/output/Replaced-arm64.app");
+        create_bundle(x86_64, "thin_x86_64");
+        create_bundle(arm64, "thin_arm64");
+        let data = x86_64.join("Contents/Resources/data");
+        fs::create_dir_all(&data).unwrap();
+        fs::write(data.join("a.txt"), "a").unwrap();
+        fs::write(data.join("b.txt"), "b").unwrap();
+        fs::write(arm64.join("Contents/Resources/data"), "data").unwrap();
+
+        let output = Path::new("tests/output/Replaced.app");
+        let mut bundle = Bundle::new(x86_64);
+        bundle.set_overwrite(true);
+        let report = bundle.merge(&Bundle::new(arm64), output).unwrap();
+        assert_eq!(
+            report.differences,
+            vec![BundleDifference::Changed("Contents/Resources/data".into())]
+        );
+        assert!(output.join("Contents/Resources/data/a.txt").exists());
+
+        // The output exists
+        let err = Bundle::new(arm64)
+            .merge(&Bundle::new(x86_64), output)
+            .unwrap_err();
+        assert!(matches!(err, crate::error::Error::Io(_)));
+        let mut bundle = Bundle::new(arm64);
+        bundle.set_overwrite(true);
+        let report = bundle.merge(&Bundle::new(x86_64), output).unwrap();
+        assert_eq!(
+            report.differences,
+            vec![BundleDifference::Changed("Contents/Resources/data".into())]
+        );
+        assert!(output.join("Contents/Resources/data").is_file());
+    }
+
+    #[test]
+    fn test_merge_bundles_output() {
+        let x86_64 = Path::new("tests/output/Output-x86_64.app");
+        let arm64 = Path::new("tests/output/Output-arm64.app");
+        create_bundle(x86_64, "thin_x86_64");
+        create_bundle(arm64, "thin_arm64");
+        let mut bundle = Bundle::new(x86_64);
+        let other = Bundle::new(arm64);
+        bundle.set_overwrite(true);
+
+        // Overlapping one of the inputs
+        for output in [x86_64.to_path_buf(), arm64.join("Contents/Merged.app")] {
+            assert!(bundle.merge(&other, &output).is_err());
+        }
+        assert!(bundle.merge(&other, "tests/output").is_err());
+        assert!(x86_64.join("Contents/Resources/Info.plist").exists());
+        assert!(!arm64.join("Contents/Merged.app").exists());
+
+        // Existing output
+        let output = Path::new("tests/output/Output.app");
+        if output.exists() {
+            fs::remove_dir_all(output).unwrap();
+        }
+        fs::create_dir_all(output).unwrap();
+        fs::write(output.join("keep"), "").unwrap();
+        bundle.set_overwrite(false);
+        assert!(bundle.merge(&other, output).is_err());
+        assert!(output.join("keep").exists());
+        bundle.set_overwrite(true);
+        bundle.merge(&other, output).unwrap();
+        assert!(!output.join("keep").exists());
+    }
+}
@@ -0,0 +1,682 @@
+//! Ad-hoc code signing and code signature inspection of thin Mach-O binaries
+use std::{error, fmt};
+
+use goblin::mach::{
+    header::{MH_DSYM, MH_EXECUTE, MH_KEXT_BUNDLE, MH_OBJECT},
+    load_command::{CommandVariant, LC_CODE_SIGNATURE, SIZEOF_LINKEDIT_DATA_COMMAND},
+    MachO,
+};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384};
+
+use crate::{
+    cputype::get_align_from_cpu_types,
+    error::Error,
+    macho::{write_u32, MachLayout},
+};
+
+const CSMAGIC_REQUIREMENTS: u32 = 0xfade0c01;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade0c02;
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
+const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade7171;
+const CSMAGIC_EMBEDDED_DER_ENTITLEMENTS: u32 = 0xfade7172;
+const CSMAGIC_BLOBWRAPPER: u32 = 0xfade0b01;
+
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_REQUIREMENTS: u32 = 2;
+const CSSLOT_ENTITLEMENTS: u32 = 5;
+const CSSLOT_DER_ENTITLEMENTS: u32 = 7;
+const CSSLOT_ALTERNATE_CODEDIRECTORIES: u32 = 0x1000;
+const CSSLOT_ALTERNATE_CODEDIRECTORY_MAX: u32 = 5;
+const CSSLOT_SIGNATURESLOT: u32 = 0x10000;
+
+const CS_ADHOC: u32 = 0x2;
+const CS_LINKER_SIGNED: u32 = 0x20000;
+const CS_HASHTYPE_SHA1: u8 = 1;
+const CS_HASHTYPE_SHA256: u8 = 2;
+const CS_HASHTYPE_SHA256_TRUNCATED: u8 = 3;
+const CS_HASHTYPE_SHA384: u8 = 4;
+/// CodeDirectory version supporting the team identifier field
+const CS_SUPPORTSTEAMID: u32 = 0x20200;
+const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+/// CodeDirectory version supporting the 64-bit code limit field
+const CS_SUPPORTSCODELIMIT64: u32 = 0x20300;
+/// CodeDirectory version supporting the exec segment fields
+const CS_SUPPORTSEXECSEG: u32 = 0x20400;
+const SIZEOF_CODEDIRECTORY: usize = 88;
+const CDHASH_SIZE: usize = 20;
+
+const PAGE_SIZE_BITS: u8 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS;
+const HASH_SIZE: usize = 32;
+
+/// Options for ad-hoc code signing
+#[derive(Debug, Clone)]
+pub struct SigningOptions {
+    /// Signing identifier, usually the bundle identifier or the file name
+    pub identifier: String,
+    /// Entitlements property list in XML format
+    pub entitlements: Option<String>,
+}
+
+impl SigningOptions {
+    /// Create signing options with the given identifier and no entitlements
+    pub fn new<S: Into<String>>(identifier: S) -> Self {
+        Self {
+            identifier: identifier.into(),
+            entitlements: None,
+        }
+    }
+}
+
+/// Whether a thin Mach-O binary of this file type can carry an ad-hoc code signature
+///
+/// dSYM companion files are never signed and kernel extensions need a real identity.
+pub(crate) fn is_signable(filetype: u32) -> bool {
+    !matches!(filetype, MH_OBJECT | MH_DSYM | MH_KEXT_BUNDLE)
+}
+
+/// Compute and embed an ad-hoc code signature into a thin Mach-O binary
+///
+/// Any existing code signature is replaced.
+pub fn adhoc_sign(macho: &[u8], options: &SigningOptions) -> Result<Vec<u8>, Error> {
+    let mut layout = MachLayout::parse(macho)?;
+    if !is_signable(layout.filetype) {
+        return Err(Error::InvalidMachO(
+            "object files, dSYM files and kernel extensions can not be ad-hoc signed".to_string(),
+        ));
+    }
+    let linkedit = layout
+        .segment("__LINKEDIT")
+        .ok_or_else(|| Error::InvalidMachO("missing __LINKEDIT segment".to_string()))?;
+    let text = layout
+        .segment("__TEXT")
+        .ok_or_else(|| Error::InvalidMachO("missing __TEXT segment".to_string()))?;
+    let existing = layout
+        .load_commands
+        .iter()
+        .find_map(|lc| match &lc.command {
+            CommandVariant::CodeSignature(cmd) => Some((lc.offset, cmd.dataoff as usize)),
+            _ => None,
+        });
+
+    // Everything before the signature is covered by the page hashes
+    let code_limit = match existing {
+        Some((_, dataoff)) => dataoff,
+        None => align_to(linkedit.fileoff + linkedit.filesize, 16) as usize,
+    };
+    if existing.is_some() && (code_limit > macho.len() || code_limit < linkedit.fileoff as usize) {
+        return Err(Error::InvalidMachO(
+            "code signature is not inside __LINKEDIT".to_string(),
+        ));
+    }
+    let mut out = macho[..code_limit.min(macho.len())].to_vec();
+    out.resize(code_limit, 0);
+    let cmd_offset = match existing {
+        Some((offset, _)) => offset,
+        None => {
+            let mut cmd = [0u8; SIZEOF_LINKEDIT_DATA_COMMAND];
+            write_u32(&mut cmd, 0, LC_CODE_SIGNATURE);
+            write_u32(&mut cmd, 4, SIZEOF_LINKEDIT_DATA_COMMAND as u32);
+            layout.add_load_command(&mut out, &cmd)?
+        }
+    };
+
+    let requirements = blob(CSMAGIC_REQUIREMENTS, &0u32.to_be_bytes());
+    let entitlements = options
+        .entitlements
+        .as_ref()
+        .map(|xml| blob(CSMAGIC_EMBEDDED_ENTITLEMENTS, xml.as_bytes()));
+    let n_special_slots = if entitlements.is_some() {
+        CSSLOT_ENTITLEMENTS
+    } else {
+        CSSLOT_REQUIREMENTS
+    } as usize;
+    let n_code_slots = code_limit.div_ceil(PAGE_SIZE);
+    let ident_offset = SIZEOF_CODEDIRECTORY;
+    let hash_offset = ident_offset + options.identifier.len() + 1 + n_special_slots * HASH_SIZE;
+    let cd_size = hash_offset + n_code_slots * HASH_SIZE;
+
+    let mut blobs = vec![(CSSLOT_CODEDIRECTORY, cd_size)];
+    blobs.push((CSSLOT_REQUIREMENTS, requirements.len()));
+    if let Some(entitlements) = &entitlements {
+        blobs.push((CSSLOT_ENTITLEMENTS, entitlements.len()));
+    }
+    let sig_size = 12 + 8 * blobs.len() + blobs.iter().map(|(_, len)| len).sum::<usize>();
+
+    // Point LC_CODE_SIGNATURE and __LINKEDIT at the new signature before hashing
+    write_u32(&mut out, cmd_offset + 8, code_limit as u32);
+    write_u32(&mut out, cmd_offset + 12, sig_size as u32);
+    let linkedit_size = (code_limit + sig_size) as u64 - linkedit.fileoff;
+    linkedit.set_filesize(&mut out, linkedit_size);
+    let page_align = get_align_from_cpu_types(layout.cputype, layout.cpusubtype).max(0x1000);
+    linkedit.set_vmsize(
+        &mut out,
+        linkedit
+            .vmsize
+            .max(align_to(linkedit_size, page_align as u64)),
+    );
+
+    let mut cd = Vec::with_capacity(cd_size);
+    cd.extend_from_slice(&CSMAGIC_CODEDIRECTORY.to_be_bytes());
+    cd.extend_from_slice(&(cd_size as u32).to_be_bytes());
+    cd.extend_from_slice(&CS_SUPPORTSEXECSEG.to_be_bytes());
+    cd.extend_from_slice(&CS_ADHOC.to_be_bytes());
+    cd.extend_from_slice(&(hash_offset as u32).to_be_bytes());
+    cd.extend_from_slice(&(ident_offset as u32).to_be_bytes());
+    cd.extend_from_slice(&(n_special_slots as u32).to_be_bytes());
+    cd.extend_from_slice(&(n_code_slots as u32).to_be_bytes());
+    cd.extend_from_slice(&(code_limit as u32).to_be_bytes());
+    cd.push(HASH_SIZE as u8);
+    cd.push(CS_HASHTYPE_SHA256);
+    cd.push(0); // platform
+    cd.push(PAGE_SIZE_BITS);
+    cd.extend_from_slice(&0u32.to_be_bytes()); // spare2
+    cd.extend_from_slice(&0u32.to_be_bytes()); // scatterOffset
+    cd.extend_from_slice(&0u32.to_be_bytes()); // teamOffset
+    cd.extend_from_slice(&0u32.to_be_bytes()); // spare3
+    cd.extend_from_slice(&0u64.to_be_bytes()); // codeLimit64
+    let exec_seg_flags = if layout.filetype == MH_EXECUTE {
+        CS_EXECSEG_MAIN_BINARY
+    } else {
+        0
+    };
+    cd.extend_from_slice(&text.fileoff.to_be_bytes());
+    cd.extend_from_slice(&text.filesize.to_be_bytes());
+    cd.extend_from_slice(&exec_seg_flags.to_be_bytes());
+    cd.extend_from_slice(options.identifier.as_bytes());
+    cd.push(0);
+    // Special slots are stored in reverse order in front of the code slots
+    for slot in (1..=n_special_slots as u32).rev() {
+        let hash = match slot {
+            CSSLOT_REQUIREMENTS => Sha256::digest(&requirements).into(),
+            CSSLOT_ENTITLEMENTS => Sha256::digest(entitlements.as_ref().unwrap()).into(),
+            _ => [0u8; HASH_SIZE],
+        };
+        cd.extend_from_slice(&hash);
+    }
+    for page in out.chunks(PAGE_SIZE) {
+        cd.extend_from_slice(&Sha256::digest(page));
+    }
+    debug_assert_eq!(cd.len(), cd_size);
+
+    // Assemble the embedded signature SuperBlob
+    out.reserve(sig_size);
+    out.extend_from_slice(&CSMAGIC_EMBEDDED_SIGNATURE.to_be_bytes());
+    out.extend_from_slice(&(sig_size as u32).to_be_bytes());
+    out.extend_from_slice(&(blobs.len() as u32).to_be_bytes());
+    let mut offset = 12 + 8 * blobs.len();
+    for (slot, len) in &blobs {
+        out.extend_from_slice(&slot.to_be_bytes());
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        offset += len;
+    }
+    out.extend_from_slice(&cd);
+    out.extend_from_slice(&requirements);
+    if let Some(entitlements) = &entitlements {
+        out.extend_from_slice(entitlements);
+    }
+    Ok(out)
+}
+
+/// Error found while parsing a code signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CodeSignatureError {
+    /// The signature, a blob or a field extends past the available data
+    Truncated,
+    /// A blob doesn't start with the expected magic number
+    BadMagic { expected: u32, found: u32 },
+    /// The signature has no CodeDirectory
+    MissingCodeDirectory,
+    /// The CodeDirectory uses an unknown hash type
+    UnsupportedHashType(u8),
+    /// A string field isn't valid NUL-terminated UTF-8
+    InvalidString,
+}
+
+impl fmt::Display for CodeSignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodeSignatureError::Truncated => write!(f, "code signature is truncated"),
+            CodeSignatureError::BadMagic { expected, found } => write!(
+                f,
+                "bad code signature blob magic 0x{:08x}, expected 0x{:08x}",
+                found, expected
+            ),
+            CodeSignatureError::MissingCodeDirectory => {
+                write!(f, "code signature has no CodeDirectory")
+            }
+            CodeSignatureError::UnsupportedHashType(ty) => {
+                write!(f, "unsupported code signature hash type {}", ty)
+            }
+            CodeSignatureError::InvalidString => {
+                write!(f, "code signature contains an invalid string")
+            }
+        }
+    }
+}
+
+impl error::Error for CodeSignatureError {}
+
+/// Hash algorithm of a CodeDirectory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashType {
+    Sha1,
+    Sha256,
+    Sha256Truncated,
+    Sha384,
+}
+
+impl HashType {
+    fn from_raw(ty: u8) -> Result<Self, CodeSignatureError> {
+        match ty {
+            CS_HASHTYPE_SHA1 => Ok(HashType::Sha1),
+            CS_HASHTYPE_SHA256 => Ok(HashType::Sha256),
+            CS_HASHTYPE_SHA256_TRUNCATED => Ok(HashType::Sha256Truncated),
+            CS_HASHTYPE_SHA384 => Ok(HashType::Sha384),
+            _ => Err(CodeSignatureError::UnsupportedHashType(ty)),
+        }
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashType::Sha1 => Sha1::digest(data).to_vec(),
+            HashType::Sha256 | HashType::Sha256Truncated => Sha256::digest(data).to_vec(),
+            HashType::Sha384 => Sha384::digest(data).to_vec(),
+        }
+    }
+}
+
+/// A decoded CodeDirectory blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDirectory {
+    pub version: u32,
+    pub flags: u32,
+    pub hash_type: HashType,
+    pub hash_size: u8,
+    /// Page size in bytes, 0 if the whole file is hashed as a single page
+    pub page_size: u32,
+    pub identifier: String,
+    pub team_id: Option<String>,
+    pub code_limit: u64,
+    pub n_special_slots: u32,
+    pub n_code_slots: u32,
+    /// Hash of the CodeDirectory blob, truncated to 20 bytes
+    pub cdhash: Vec<u8>,
+}
+
+impl CodeDirectory {
+    /// Whether the code is ad-hoc signed
+    pub fn is_adhoc(&self) -> bool {
+        self.flags & CS_ADHOC != 0
+    }
+
+    /// Whether the signature was generated by the linker
+    pub fn is_linker_signed(&self) -> bool {
+        self.flags & CS_LINKER_SIGNED != 0
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, CodeSignatureError> {
+        expect_magic(data, CSMAGIC_CODEDIRECTORY)?;
+        let version = be_u32(data, 8)?;
+        let flags = be_u32(data, 12)?;
+        let ident_offset = be_u32(data, 20)? as usize;
+        let n_special_slots = be_u32(data, 24)?;
+        let n_code_slots = be_u32(data, 28)?;
+        let code_limit = be_u32(data, 32)? as u64;
+        let header = data.get(36..40).ok_or(CodeSignatureError::Truncated)?;
+        let (hash_size, hash_type, page_size_bits) = (header[0], header[1], header[3]);
+        let hash_type = HashType::from_raw(hash_type)?;
+        let mut team_id = None;
+        if version >= CS_SUPPORTSTEAMID {
+            let team_offset = be_u32(data, 48)? as usize;
+            if team_offset != 0 {
+                team_id = Some(c_string(data, team_offset)?);
+            }
+        }
+        let mut code_limit = code_limit;
+        if version >= CS_SUPPORTSCODELIMIT64 {
+            let code_limit64 = be_u64(data, 56)?;
+            if code_limit64 != 0 {
+                code_limit = code_limit64;
+            }
+        }
+        let mut cdhash = hash_type.digest(data);
+        cdhash.truncate(CDHASH_SIZE);
+        Ok(Self {
+            version,
+            flags,
+            hash_type,
+            hash_size,
+            page_size: match page_size_bits {
+                0 => 0,
+                bits => 1u32
+                    .checked_shl(bits as u32)
+                    .ok_or(CodeSignatureError::Truncated)?,
+            },
+            identifier: c_string(data, ident_offset)?,
+            team_id,
+            code_limit,
+            n_special_slots,
+            n_code_slots,
+            cdhash,
+        })
+    }
+}
+
+/// A decoded embedded code signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeSignature {
+    /// The primary CodeDirectory
+    pub code_directory: CodeDirectory,
+    /// Alternate CodeDirectories using other hash types
+    pub alternate_code_directories: Vec<CodeDirectory>,
+    /// Entitlements property list in XML format
+    pub entitlements: Option<String>,
+    /// DER encoded entitlements
+    pub der_entitlements: Option<Vec<u8>>,
+    /// Size of the CMS signature, `None` or 0 for ad-hoc signatures
+    pub cms_size: Option<usize>,
+}
+
+impl CodeSignature {
+    /// Whether the code is ad-hoc signed rather than signed with a certificate
+    pub fn is_adhoc(&self) -> bool {
+        self.code_directory.is_adhoc() || self.cms_size.unwrap_or(0) == 0
+    }
+
+    /// Parse an embedded signature SuperBlob
+    pub fn parse(data: &[u8]) -> Result<Self, CodeSignatureError> {
+        expect_magic(data, CSMAGIC_EMBEDDED_SIGNATURE)?;
+        let length = be_u32(data, 4)? as usize;
+        let data = data.get(..length).ok_or(CodeSignatureError::Truncated)?;
+        let count = be_u32(data, 8)? as usize;
+        let mut code_directory = None;
+        let mut alternate_code_directories = Vec::new();
+        let mut entitlements = None;
+        let mut der_entitlements = None;
+        let mut cms_size = None;
+        for i in 0..count {
+            let slot = be_u32(data, 12 + i * 8)?;
+            let offset = be_u32(data, 16 + i * 8)? as usize;
+            let blob_length = be_u32(data, offset + 4)? as usize;
+            let blob = offset
+                .checked_add(blob_length)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(CodeSignatureError::Truncated)?;
+            // Every blob starts with its magic number and length
+            if blob_length < 8 {
+                return Err(CodeSignatureError::Truncated);
+            }
+            match slot {
+                CSSLOT_CODEDIRECTORY => code_directory = Some(CodeDirectory::parse(blob)?),
+                CSSLOT_ENTITLEMENTS => {
+                    expect_magic(blob, CSMAGIC_EMBEDDED_ENTITLEMENTS)?;
+                    let xml = String::from_utf8(blob[8..].to_vec())
+                        .map_err(|_| CodeSignatureError::InvalidString)?;
+                    entitlements = Some(xml);
+                }
+                CSSLOT_DER_ENTITLEMENTS => {
+                    expect_magic(blob, CSMAGIC_EMBEDDED_DER_ENTITLEMENTS)?;
+                    der_entitlements = Some(blob[8..].to_vec());
+                }
+                CSSLOT_SIGNATURESLOT => {
+                    expect_magic(blob, CSMAGIC_BLOBWRAPPER)?;
+                    cms_size = Some(blob.len() - 8);
+                }
+                slot if (CSSLOT_ALTERNATE_CODEDIRECTORIES
+                    ..CSSLOT_ALTERNATE_CODEDIRECTORIES + CSSLOT_ALTERNATE_CODEDIRECTORY_MAX)
+                    .contains(&slot) =>
+                {
+                    alternate_code_directories.push(CodeDirectory::parse(blob)?)
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            code_directory: code_directory.ok_or(CodeSignatureError::MissingCodeDirectory)?,
+            alternate_code_directories,
+            entitlements,
+            der_entitlements,
+            cms_size,
+        })
+    }
+}
+
+/// Parse the embedded code signature of a thin Mach-O binary, if it has one
+pub fn code_signature(macho: &[u8]) -> Result<Option<CodeSignature>, Error> {
+    let mach = MachO::parse(macho, 0)?;
+    let cmd = mach.load_commands.iter().find_map(|lc| match &lc.command {
+        CommandVariant::CodeSignature(cmd) => Some(*cmd),
+        _ => None,
+    });
+    let cmd = match cmd {
+        Some(cmd) => cmd,
+        None => return Ok(None),
+    };
+    let start = cmd.dataoff as usize;
+    let data = start
+        .checked_add(cmd.datasize as usize)
+        .and_then(|end| macho.get(start..end))
+        .ok_or(CodeSignatureError::Truncated)?;
+    Ok(Some(CodeSignature::parse(data)?))
+}
+
+fn expect_magic(data: &[u8], expected: u32) -> Result<(), CodeSignatureError> {
+    let found = be_u32(data, 0)?;
+    if found != expected {
+        return Err(CodeSignatureError::BadMagic { expected, found });
+    }
+    Ok(())
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Result<u32, CodeSignatureError> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or(CodeSignatureError::Truncated)
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Result<u64, CodeSignatureError> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or(CodeSignatureError::Truncated)
+}
+
+fn c_string(data: &[u8], offset: usize) -> Result<String, CodeSignatureError> {
+    let bytes = data.get(offset..).ok_or(CodeSignatureError::Truncated)?;
+    let end = bytes
+        .iter()
+        .position(|&c| c == 0)
+        .ok_or(CodeSignatureError::InvalidString)?;
+    String::from_utf8(bytes[..end].to_vec()).map_err(|_| CodeSignatureError::InvalidString)
+}
+
+/// Wrap `data` into a code signing blob
+fn blob(magic: u32, data: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(8 + data.len());
+    blob.extend_from_slice(&magic.to_be_bytes());
+    blob.extend_from_slice(&(8 + data.len() as u32).to_be_bytes());
+    blob.extend_from_slice(data);
+    blob
+}
+
+#[inline]
+fn align_to(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, ops::Range};
+
+    use goblin::mach::{load_command::CommandVariant, MachO};
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        adhoc_sign, code_signature, CodeSignature, CodeSignatureError, HashType, SigningOptions,
+        CSMAGIC_BLOBWRAPPER, CSMAGIC_EMBEDDED_ENTITLEMENTS, CSMAGIC_EMBEDDED_SIGNATURE,
+        CSSLOT_ENTITLEMENTS, CSSLOT_SIGNATURESLOT,
+    };
+    use crate::{error::Error, macho::MachLayout};
+
+    /// File range of the code signature from `LC_CODE_SIGNATURE`
+    fn signature_range(macho: &[u8]) -> Range<usize> {
+        let cmd = MachO::parse(macho, 0)
+            .unwrap()
+            .load_commands
+            .iter()
+            .find_map(|lc| match &lc.command {
+                CommandVariant::CodeSignature(cmd) => Some(*cmd),
+                _ => None,
+            })
+            .unwrap();
+        cmd.dataoff as usize..(cmd.dataoff + cmd.datasize) as usize
+    }
+
+    fn check_signature(signed: &[u8]) {
+        let range = signature_range(signed);
+        let dataoff = range.start;
+        assert_eq!(range.end, signed.len());
+        let linkedit = MachLayout::parse(signed)
+            .unwrap()
+            .segment("__LINKEDIT")
+            .unwrap();
+        assert_eq!(linkedit.fileoff + linkedit.filesize, signed.len() as u64);
+
+        let sig = &signed[dataoff..];
+        assert_eq!(&sig[..4], &[0xfa, 0xde, 0x0c, 0xc0]);
+        let cd_offset = u32::from_be_bytes(sig[16..20].try_into().unwrap()) as usize;
+        let cd = &sig[cd_offset..];
+        assert_eq!(&cd[..4], &[0xfa, 0xde, 0x0c, 0x02]);
+        let hash_offset = u32::from_be_bytes(cd[16..20].try_into().unwrap()) as usize;
+        let n_code_slots = u32::from_be_bytes(cd[28..32].try_into().unwrap()) as usize;
+        let code_limit = u32::from_be_bytes(cd[32..36].try_into().unwrap()) as usize;
+        assert_eq!(code_limit, dataoff);
+        for (i, page) in signed[..code_limit].chunks(4096).enumerate() {
+            assert!(i < n_code_slots);
+            let hash = &cd[hash_offset + i * 32..hash_offset + (i + 1) * 32];
+            assert_eq!(hash, Sha256::digest(page).as_slice());
+        }
+    }
+
+    #[test]
+    fn test_adhoc_sign_unsigned() {
+        let buf = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let signed = adhoc_sign(&buf, &SigningOptions::new("thin_x86_64")).unwrap();
+        check_signature(&signed);
+    }
+
+    #[test]
+    fn test_adhoc_sign_unsignable() {
+        let options = SigningOptions::new("unsignable");
+        for fixture in ["bitcode_arm64.o", "thin_x86_64.dsym"] {
+            let buf = fs::read(format!("tests/fixtures/{}", fixture)).unwrap();
+            assert!(adhoc_sign(&buf, &options).is_err(), "{}", fixture);
+        }
+    }
+
+    #[test]
+    fn test_adhoc_sign_resign() {
+        let buf = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let mut options = SigningOptions::new("com.example.thin");
+        options.entitlements = Some(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>com.apple.security.get-task-allow</key>
+    <true/>
+</dict>
+</plist>
+"#
+            .to_string(),
+        );
+        let signed = adhoc_sign(&buf, &options).unwrap();
+        check_signature(&signed);
+        // Signing is deterministic
+        assert_eq!(signed, adhoc_sign(&signed, &options).unwrap());
+    }
+
+    #[test]
+    fn test_code_signature_linker_signed() {
+        let buf = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let sig = code_signature(&buf).unwrap().unwrap();
+        let cd = &sig.code_directory;
+        assert_eq!(cd.identifier, "thin_arm64");
+        assert_eq!(cd.hash_type, HashType::Sha256);
+        assert_eq!(cd.page_size, 4096);
+        assert!(cd.is_adhoc());
+        assert!(cd.is_linker_signed());
+        assert!(cd.team_id.is_none());
+        assert_eq!(cd.cdhash.len(), 20);
+        assert!(sig.is_adhoc());
+        assert!(sig.entitlements.is_none());
+
+        let buf = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert!(code_signature(&buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_code_signature_entitlements() {
+        let buf = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let mut options = SigningOptions::new("com.example.thin");
+        options.entitlements = Some("<plist><dict/></plist>".to_string());
+        let signed = adhoc_sign(&buf, &options).unwrap();
+        let sig = code_signature(&signed).unwrap().unwrap();
+        assert_eq!(sig.code_directory.identifier, "com.example.thin");
+        assert_eq!(sig.code_directory.n_special_slots, 5);
+        assert!(!sig.code_directory.is_linker_signed());
+        assert_eq!(sig.entitlements.as_deref(), Some("<plist><dict/></plist>"));
+        assert!(sig.cms_size.is_none());
+    }
+
+    #[test]
+    fn test_code_signature_corrupted() {
+        let buf = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let sig = &buf[signature_range(&buf)];
+        assert_eq!(
+            CodeSignature::parse(&sig[..100]).unwrap_err(),
+            CodeSignatureError::Truncated
+        );
+        let mut corrupted = sig.to_vec();
+        corrupted[20] = 0;
+        assert!(matches!(
+            CodeSignature::parse(&corrupted).unwrap_err(),
+            CodeSignatureError::BadMagic { .. }
+        ));
+
+        // Signature data extending past the end of the file
+        let offset = MachO::parse(&buf, 0)
+            .unwrap()
+            .load_commands
+            .iter()
+            .find(|lc| matches!(lc.command, CommandVariant::CodeSignature(_)))
+            .unwrap()
+            .offset;
+        let mut corrupted = buf.clone();
+        corrupted[offset + 12..offset + 16].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert!(matches!(
+            code_signature(&corrupted).unwrap_err(),
+            Error::CodeSignature(CodeSignatureError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_code_signature_short_blob() {
+        for (slot, magic) in [
+            (CSSLOT_SIGNATURESLOT, CSMAGIC_BLOBWRAPPER),
+            (CSSLOT_ENTITLEMENTS, CSMAGIC_EMBEDDED_ENTITLEMENTS),
+        ] {
+            // A SuperBlob with a single blob whose length doesn't cover its own header
+            let sig: Vec<u8> = [CSMAGIC_EMBEDDED_SIGNATURE, 28, 1, slot, 20, magic, 4]
+                .iter()
+                .flat_map(|field| field.to_be_bytes())
+                .collect();
+            assert_eq!(
+                CodeSignature::parse(&sig).unwrap_err(),
+                CodeSignatureError::Truncated
+            );
+        }
+    }
+}
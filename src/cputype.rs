@@ -0,0 +1,316 @@
+//! Architecture names and alignments, including CPU types registered by callers
+use std::{
+    str::FromStr,
+    sync::{PoisonError, RwLock},
+};
+
+use goblin::mach::cputype::{
+    get_arch_from_flag, get_arch_name_from_types, CpuSubType, CpuType, CPU_SUBTYPE_ARM64_E,
+    CPU_SUBTYPE_MASK, CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_HPPA,
+    CPU_TYPE_I386, CPU_TYPE_I860, CPU_TYPE_MC680X0, CPU_TYPE_MC88000, CPU_TYPE_POWERPC,
+    CPU_TYPE_POWERPC64, CPU_TYPE_SPARC, CPU_TYPE_X86_64,
+};
+
+use crate::error::Error;
+
+/// Alignment of Mach-O slices with an unknown CPU type, the largest page size in use
+pub const DEFAULT_ALIGN: u32 = 0x4000;
+
+/// A cputype and cpusubtype pair identifying the architecture of a slice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Arch {
+    pub cpu_type: CpuType,
+    pub cpu_subtype: CpuSubType,
+}
+
+impl Arch {
+    pub fn new(cpu_type: CpuType, cpu_subtype: CpuSubType) -> Self {
+        Self {
+            cpu_type,
+            cpu_subtype,
+        }
+    }
+
+    /// Name of the architecture, including registered custom CPU types
+    pub fn name(&self) -> Option<&'static str> {
+        arch_name(self.cpu_type, self.cpu_subtype)
+    }
+}
+
+impl FromStr for Arch {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        arch_from_name(name)
+            .map(|(cpu_type, cpu_subtype)| Self::new(cpu_type, cpu_subtype))
+            .ok_or_else(|| Error::InvalidMachO(format!("unknown architecture {}", name)))
+    }
+}
+
+/// Flag of arm64e cpusubtypes carrying a pointer authentication ABI version
+pub const CPU_SUBTYPE_PTRAUTH_ABI: CpuSubType = 0x8000_0000;
+/// Flag of arm64e cpusubtypes using the kernel pointer authentication ABI
+pub const CPU_SUBTYPE_PTRAUTH_KERNEL_ABI: CpuSubType = 0x4000_0000;
+const CPU_SUBTYPE_PTRAUTH_VERSION_MASK: CpuSubType = 0x0f00_0000;
+const CPU_SUBTYPE_PTRAUTH_VERSION_SHIFT: u32 = 24;
+
+/// Versioned pointer authentication ABI of an arm64e slice
+///
+/// Encoded in the high bits of the cpusubtype together with [`CPU_SUBTYPE_PTRAUTH_ABI`],
+/// the plain arm64e cpusubtype has no ABI version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PtrAuthAbi {
+    /// ABI version, from 0 to 15
+    pub version: u8,
+    /// Whether the kernel ABI is used
+    pub kernel: bool,
+}
+
+impl PtrAuthAbi {
+    /// Decode the ABI of an arm64e cpusubtype, `None` if it isn't versioned
+    pub fn from_cpusubtype(cpusubtype: CpuSubType) -> Option<Self> {
+        let flags = CPU_SUBTYPE_PTRAUTH_ABI
+            | CPU_SUBTYPE_PTRAUTH_KERNEL_ABI
+            | CPU_SUBTYPE_PTRAUTH_VERSION_MASK;
+        if cpusubtype & !flags != CPU_SUBTYPE_ARM64_E || cpusubtype & CPU_SUBTYPE_PTRAUTH_ABI == 0 {
+            return None;
+        }
+        Some(Self {
+            version: ((cpusubtype & CPU_SUBTYPE_PTRAUTH_VERSION_MASK)
+                >> CPU_SUBTYPE_PTRAUTH_VERSION_SHIFT) as u8,
+            kernel: cpusubtype & CPU_SUBTYPE_PTRAUTH_KERNEL_ABI != 0,
+        })
+    }
+
+    /// The arm64e cpusubtype with this ABI, fails if the version doesn't fit in 4 bits
+    pub fn cpusubtype(&self) -> Result<CpuSubType, Error> {
+        if self.version > 0xf {
+            return Err(Error::InvalidMachO(format!(
+                "pointer authentication ABI version {} is larger than 15",
+                self.version
+            )));
+        }
+        let mut cpusubtype = CPU_SUBTYPE_ARM64_E
+            | CPU_SUBTYPE_PTRAUTH_ABI
+            | (self.version as CpuSubType) << CPU_SUBTYPE_PTRAUTH_VERSION_SHIFT;
+        if self.kernel {
+            cpusubtype |= CPU_SUBTYPE_PTRAUTH_KERNEL_ABI;
+        }
+        Ok(cpusubtype)
+    }
+}
+
+/// A CPU type goblin doesn't know, see [`register_cpu_type`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CustomCpuType {
+    pub name: &'static str,
+    pub cpu_type: CpuType,
+    pub cpu_subtype: CpuSubType,
+    /// Alignment of Mach-O slices in bytes, must be a power of two
+    pub align: u32,
+}
+
+static CUSTOM_CPU_TYPES: RwLock<Vec<CustomCpuType>> = RwLock::new(Vec::new());
+
+/// Register the name and alignment of a new or experimental CPU type
+///
+/// The registry is global, it applies to every reader and writer. Registering the
+/// same CPU type twice is fine, clashing with a known name or CPU type is not.
+pub fn register_cpu_type(cpu: CustomCpuType) -> Result<(), Error> {
+    if !cpu.align.is_power_of_two() {
+        return Err(Error::InvalidAlignment {
+            arch: cpu.name.to_string(),
+            align: cpu.align as u64,
+        });
+    }
+    if get_arch_from_flag(cpu.name).is_some()
+        || known_arch_name(cpu.cpu_type, cpu.cpu_subtype).is_some()
+    {
+        return Err(Error::DuplicatedArch(cpu.name.to_string()));
+    }
+    let mut registry = CUSTOM_CPU_TYPES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    match registry.iter().find(|custom| {
+        custom.name == cpu.name
+            || (custom.cpu_type == cpu.cpu_type && custom.cpu_subtype == cpu.cpu_subtype)
+    }) {
+        Some(custom) if *custom == cpu => Ok(()),
+        Some(_) => Err(Error::DuplicatedArch(cpu.name.to_string())),
+        None => {
+            registry.push(cpu);
+            Ok(())
+        }
+    }
+}
+
+fn find_custom(predicate: impl Fn(&CustomCpuType) -> bool) -> Option<CustomCpuType> {
+    CUSTOM_CPU_TYPES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .copied()
+        .find(predicate)
+}
+
+/// Name of an architecture known to goblin
+///
+/// Capability bits of the cpusubtype, such as the versioned ABI of arm64e, are ignored.
+fn known_arch_name(cpu_type: CpuType, cpu_subtype: CpuSubType) -> Option<&'static str> {
+    get_arch_name_from_types(cpu_type, cpu_subtype)
+        .or_else(|| get_arch_name_from_types(cpu_type, cpu_subtype & !CPU_SUBTYPE_MASK))
+}
+
+/// Name of an architecture
+pub(crate) fn arch_name(cpu_type: CpuType, cpu_subtype: CpuSubType) -> Option<&'static str> {
+    known_arch_name(cpu_type, cpu_subtype).or_else(|| {
+        find_custom(|custom| custom.cpu_type == cpu_type && custom.cpu_subtype == cpu_subtype)
+            .map(|custom| custom.name)
+    })
+}
+
+/// cputype and cpusubtype of an architecture name
+pub(crate) fn arch_from_name(name: &str) -> Option<(CpuType, CpuSubType)> {
+    get_arch_from_flag(name).or_else(|| {
+        find_custom(|custom| custom.name == name)
+            .map(|custom| (custom.cpu_type, custom.cpu_subtype))
+    })
+}
+
+/// Whether a slice has the named architecture, using the same names as [`arch_name`]
+pub(crate) fn is_arch(name: &str, cpu_type: CpuType, cpu_subtype: CpuSubType) -> bool {
+    arch_name(cpu_type, cpu_subtype) == Some(name)
+        || arch_from_name(name) == Some((cpu_type, cpu_subtype))
+}
+
+/// Alignment of a Mach-O slice, falls back to [`DEFAULT_ALIGN`] for unknown CPU types
+pub(crate) fn get_align_from_cpu_types(cpu_type: CpuType, cpu_subtype: CpuSubType) -> i64 {
+    if let Some(arch_name) = known_arch_name(cpu_type, cpu_subtype) {
+        if let Some((cpu_type, _)) = get_arch_from_flag(arch_name) {
+            match cpu_type {
+                // embedded
+                CPU_TYPE_ARM | CPU_TYPE_ARM64 | CPU_TYPE_ARM64_32 => return 0x4000,
+                // desktop
+                CPU_TYPE_X86_64 | CPU_TYPE_I386 | CPU_TYPE_POWERPC | CPU_TYPE_POWERPC64 => {
+                    return 0x1000
+                }
+                CPU_TYPE_MC680X0 | CPU_TYPE_MC88000 | CPU_TYPE_SPARC | CPU_TYPE_I860
+                | CPU_TYPE_HPPA => return 0x2000,
+                _ => {}
+            }
+        }
+    }
+    find_custom(|custom| custom.cpu_type == cpu_type && custom.cpu_subtype == cpu_subtype)
+        .map_or(DEFAULT_ALIGN, |custom| custom.align) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use goblin::mach::cputype::{CPU_SUBTYPE_ARM64_E, CPU_TYPE_ARM64};
+
+    use super::{
+        arch_name, get_align_from_cpu_types, register_cpu_type, CustomCpuType, PtrAuthAbi,
+        DEFAULT_ALIGN,
+    };
+    use crate::{read::FatReader, write::FatWriter};
+
+    const CPU_TYPE_RISCV64: u32 = 24 | 0x0100_0000;
+
+    #[test]
+    fn test_ptrauth_abi() {
+        assert_eq!(PtrAuthAbi::from_cpusubtype(CPU_SUBTYPE_ARM64_E), None);
+        let abi = PtrAuthAbi::from_cpusubtype(0x8000_0002).unwrap();
+        assert_eq!((abi.version, abi.kernel), (0, false));
+        let abi = PtrAuthAbi::from_cpusubtype(0xc100_0002).unwrap();
+        assert_eq!((abi.version, abi.kernel), (1, true));
+        assert_eq!(abi.cpusubtype().unwrap(), 0xc100_0002);
+        assert!(PtrAuthAbi {
+            version: 16,
+            kernel: false
+        }
+        .cpusubtype()
+        .is_err());
+        // Only arm64e has a pointer authentication ABI
+        assert_eq!(PtrAuthAbi::from_cpusubtype(0x8000_0000), None);
+
+        assert_eq!(arch_name(CPU_TYPE_ARM64, 0x8000_0002), Some("arm64e"));
+        assert_eq!(arch_name(CPU_TYPE_ARM64, 0x8100_0002), Some("arm64e"));
+        assert_eq!(
+            get_align_from_cpu_types(CPU_TYPE_ARM64, 0x8000_0002),
+            0x4000
+        );
+    }
+
+    #[test]
+    fn test_versioned_arm64e_lookup() {
+        let arm64 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let mut arm64e = arm64.clone();
+        arm64e[8..12].copy_from_slice(&0x8100_0002u32.to_le_bytes());
+
+        let mut fat = FatWriter::new();
+        fat.add(arm64.clone()).unwrap();
+        fat.add(arm64e.clone()).unwrap();
+        assert_eq!(fat.arches(), vec!["arm64", "arm64e"]);
+        assert!(fat.exists("arm64e"));
+        assert_eq!(fat.get("arm64e"), Some(arm64e.as_slice()));
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.extract("arm64e"), Some(arm64e.as_slice()));
+        assert_eq!(reader.extract("arm64"), Some(arm64.as_slice()));
+
+        assert_eq!(fat.remove("arm64e"), Some(arm64e));
+        assert_eq!(fat.arches(), vec!["arm64"]);
+    }
+
+    #[test]
+    fn test_custom_cpu_type() {
+        let mut riscv64 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        riscv64[4..8].copy_from_slice(&CPU_TYPE_RISCV64.to_le_bytes());
+        riscv64[8..12].copy_from_slice(&0u32.to_le_bytes());
+
+        // Unknown CPU types use the default alignment
+        let mut fat = FatWriter::new();
+        fat.add(riscv64.clone()).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let arch = reader.iter_arches().next().unwrap();
+        assert_eq!(arch.offset, DEFAULT_ALIGN as u64);
+
+        let custom = CustomCpuType {
+            name: "riscv64",
+            cpu_type: CPU_TYPE_RISCV64,
+            cpu_subtype: 0,
+            align: 0x1000,
+        };
+        register_cpu_type(custom).unwrap();
+        register_cpu_type(custom).unwrap();
+        assert!(register_cpu_type(CustomCpuType { align: 3, ..custom }).is_err());
+        assert!(register_cpu_type(CustomCpuType {
+            name: "x86_64",
+            ..custom
+        })
+        .is_err());
+        assert!(register_cpu_type(CustomCpuType {
+            cpu_subtype: 1,
+            ..custom
+        })
+        .is_err());
+
+        let mut fat = FatWriter::new();
+        fat.add(riscv64.clone()).unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        assert_eq!(fat.arches(), vec!["riscv64", "arm64"]);
+        assert_eq!(fat.get("riscv64"), Some(riscv64.as_slice()));
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.slices().unwrap()[0].arch, "riscv64");
+        assert_eq!(reader.extract("riscv64"), Some(riscv64.as_slice()));
+    }
+}
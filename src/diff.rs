@@ -0,0 +1,368 @@
+//! Slice by slice comparison of fat binaries
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "codesign")]
+use crate::{codesign::code_signature, info::PayloadKind};
+use crate::{error::Error, info::SliceInfo, read::FatReader};
+
+/// Identity of the code signature of a slice, compared by [`diff`]
+///
+/// Only filled in with the `codesign` feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SignatureIdentity {
+    /// Hash of the primary CodeDirectory, truncated to 20 bytes
+    pub cdhash: Vec<u8>,
+    pub adhoc: bool,
+    pub team_id: Option<String>,
+    /// Why the signature couldn't be parsed, the other fields are empty then
+    pub error: Option<String>,
+}
+
+impl SignatureIdentity {
+    #[cfg(feature = "codesign")]
+    fn parse(slice: &SliceInfo, data: &[u8]) -> Option<Self> {
+        if slice.kind != PayloadKind::MachO || !slice.signed {
+            return None;
+        }
+        match code_signature(data) {
+            Ok(signature) => signature.map(|signature| Self {
+                cdhash: signature.code_directory.cdhash.clone(),
+                adhoc: signature.is_adhoc(),
+                team_id: signature.code_directory.team_id,
+                error: None,
+            }),
+            Err(err) => Some(Self {
+                cdhash: Vec::new(),
+                adhoc: false,
+                team_id: None,
+                error: Some(err.to_string()),
+            }),
+        }
+    }
+
+    #[cfg(not(feature = "codesign"))]
+    fn parse(_slice: &SliceInfo, _data: &[u8]) -> Option<Self> {
+        None
+    }
+}
+
+impl fmt::Display for SignatureIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(error) = &self.error {
+            return write!(f, "invalid ({})", error);
+        }
+        match &self.team_id {
+            Some(team_id) => write!(f, "team {}", team_id)?,
+            None if self.adhoc => write!(f, "ad-hoc")?,
+            None => write!(f, "signed")?,
+        }
+        write!(f, " cdhash ")?;
+        for byte in &self.cdhash {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Difference of an architecture present in both fat binaries
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SliceDiff {
+    pub old: SliceInfo,
+    pub new: SliceInfo,
+    /// Whether the slice contents differ
+    pub content_changed: bool,
+    /// Code signature of the old slice, `None` if it isn't signed or without the `codesign` feature
+    pub old_signature: Option<SignatureIdentity>,
+    /// Code signature of the new slice, `None` if it isn't signed or without the `codesign` feature
+    pub new_signature: Option<SignatureIdentity>,
+}
+
+impl SliceDiff {
+    /// Size change in bytes
+    pub fn size_delta(&self) -> i64 {
+        self.new.size as i64 - self.old.size as i64
+    }
+
+    /// Whether anything but the slice offset changed
+    pub fn is_changed(&self) -> bool {
+        self.content_changed || self.old.align != self.new.align || self.signature_changed()
+    }
+
+    /// Whether the code signatures differ, in their presence, cdhash, kind or team ID
+    ///
+    /// A signature that can't be parsed always counts as a difference.
+    pub fn signature_changed(&self) -> bool {
+        let invalid = |signature: &Option<SignatureIdentity>| {
+            matches!(signature, Some(SignatureIdentity { error: Some(_), .. }))
+        };
+        self.old.signed != self.new.signed
+            || self.old_signature != self.new_signature
+            || invalid(&self.old_signature)
+            || invalid(&self.new_signature)
+    }
+}
+
+/// Structural difference between two fat binaries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FatDiff {
+    /// Architectures only present in the new fat binary
+    pub added: Vec<SliceInfo>,
+    /// Architectures only present in the old fat binary
+    pub removed: Vec<SliceInfo>,
+    /// Architectures present in both fat binaries whose slices differ
+    pub changed: Vec<SliceDiff>,
+    /// Architectures present in both fat binaries with identical slices
+    pub unchanged: Vec<String>,
+}
+
+impl FatDiff {
+    /// Whether both fat binaries contain the same slices
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two fat binaries slice by slice
+pub fn diff(old: &FatReader, new: &FatReader) -> Result<FatDiff, Error> {
+    let old_slices = old.slices()?;
+    let mut new_slices = new.slices()?;
+    let mut result = FatDiff::default();
+    for old_slice in old_slices {
+        let position = new_slices.iter().position(|slice| {
+            slice.cputype == old_slice.cputype && slice.cpusubtype == old_slice.cpusubtype
+        });
+        let new_slice = match position {
+            Some(index) => new_slices.remove(index),
+            None => {
+                result.removed.push(old_slice);
+                continue;
+            }
+        };
+        let old_data = old.slice_data(&old_slice);
+        let new_data = new.slice_data(&new_slice);
+        let slice_diff = SliceDiff {
+            content_changed: old_data != new_data,
+            old_signature: SignatureIdentity::parse(&old_slice, old_data),
+            new_signature: SignatureIdentity::parse(&new_slice, new_data),
+            old: old_slice,
+            new: new_slice,
+        };
+        if slice_diff.is_changed() {
+            result.changed.push(slice_diff);
+        } else {
+            result.unchanged.push(slice_diff.new.arch);
+        }
+    }
+    result.added = new_slices;
+    Ok(result)
+}
+
+fn fmt_option<T: fmt::Display>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+impl fmt::Display for SliceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (old, new) = (&self.old, &self.new);
+        write!(f, "~ {}:", new.arch)?;
+        if old.size != new.size {
+            write!(
+                f,
+                " size {} -> {} ({:+}),",
+                old.size,
+                new.size,
+                self.size_delta()
+            )?;
+        }
+        if old.align != new.align {
+            write!(f, " align 2^{} -> 2^{},", old.align, new.align)?;
+        }
+        if old.uuid != new.uuid {
+            write!(
+                f,
+                " uuid {} -> {},",
+                fmt_option(&old.uuid),
+                fmt_option(&new.uuid)
+            )?;
+        }
+        if old.platform != new.platform || old.min_os != new.min_os {
+            write!(
+                f,
+                " min os {} {} -> {} {},",
+                fmt_option(&old.platform),
+                fmt_option(&old.min_os),
+                fmt_option(&new.platform),
+                fmt_option(&new.min_os)
+            )?;
+        }
+        if self.signature_changed() {
+            let signature = |signature: &Option<SignatureIdentity>, signed| match signature {
+                Some(signature) => signature.to_string(),
+                None if signed => "signed".to_string(),
+                None => "unsigned".to_string(),
+            };
+            write!(
+                f,
+                " signature {} -> {},",
+                signature(&self.old_signature, old.signed),
+                signature(&self.new_signature, new.signed)
+            )?;
+        }
+        if self.content_changed {
+            write!(f, " contents changed")
+        } else {
+            write!(f, " contents unchanged")
+        }
+    }
+}
+
+impl fmt::Display for FatDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        for slice in &self.added {
+            writeln!(f, "+ {} ({} bytes)", slice.arch, slice.size)?;
+        }
+        for slice in &self.removed {
+            writeln!(f, "- {} ({} bytes)", slice.arch, slice.size)?;
+        }
+        for slice in &self.changed {
+            writeln!(f, "{}", slice)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::diff;
+    use crate::{read::FatReader, write::FatWriter};
+
+    #[test]
+    fn test_diff_identical() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let result = diff(&reader, &reader).unwrap();
+        assert!(result.is_empty());
+        assert_eq!(result.unchanged, vec!["x86_64", "x86_64h", "arm64"]);
+        assert_eq!(result.to_string(), "no differences\n");
+    }
+
+    #[test]
+    fn test_diff_changed() {
+        let old = fs::read("tests/fixtures/hellofat").unwrap();
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        let mut new = Vec::new();
+        fat.write_to(&mut new).unwrap();
+
+        let result = diff(
+            &FatReader::new(&old).unwrap(),
+            &FatReader::new(&new).unwrap(),
+        )
+        .unwrap();
+        assert!(result.added.is_empty());
+        assert_eq!(result.removed.len(), 1);
+        assert_eq!(result.removed[0].arch, "x86_64h");
+        assert_eq!(result.changed.len(), 2);
+        let x86_64 = &result.changed[0];
+        assert_eq!(x86_64.new.arch, "x86_64");
+        assert!(x86_64.content_changed);
+        assert_eq!(x86_64.size_delta(), 16536 - 49424);
+        let text = result.to_string();
+        assert!(
+            text.starts_with("- x86_64h (49424 bytes)\n~ x86_64: size 49424 -> 16536 (-32888),")
+        );
+    }
+
+    #[cfg(feature = "codesign")]
+    #[test]
+    fn test_diff_signature() {
+        use crate::codesign::{adhoc_sign, SigningOptions};
+
+        let thin = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let write = |identifier: Option<&str>| {
+            let mut fat = FatWriter::new();
+            match identifier {
+                Some(identifier) => fat
+                    .add(adhoc_sign(&thin, &SigningOptions::new(identifier)).unwrap())
+                    .unwrap(),
+                None => fat.add(thin.clone()).unwrap(),
+            }
+            let mut out = Vec::new();
+            fat.write_to(&mut out).unwrap();
+            out
+        };
+        let unsigned = write(None);
+        let signed = write(Some("com.example.one"));
+        let resigned = write(Some("com.example.two"));
+
+        let result = diff(
+            &FatReader::new(&signed).unwrap(),
+            &FatReader::new(&resigned).unwrap(),
+        )
+        .unwrap();
+        let slice = &result.changed[0];
+        assert!(slice.signature_changed());
+        let (old, new) = (
+            slice.old_signature.as_ref().unwrap(),
+            slice.new_signature.as_ref().unwrap(),
+        );
+        assert!(old.adhoc && new.adhoc);
+        assert_ne!(old.cdhash, new.cdhash);
+        assert!(result.to_string().contains(" signature ad-hoc cdhash "));
+
+        let result = diff(
+            &FatReader::new(&unsigned).unwrap(),
+            &FatReader::new(&signed).unwrap(),
+        )
+        .unwrap();
+        assert!(result.changed[0].old_signature.is_none());
+        assert!(result
+            .to_string()
+            .contains(" signature unsigned -> ad-hoc cdhash "));
+
+        let result = diff(
+            &FatReader::new(&signed).unwrap(),
+            &FatReader::new(&signed).unwrap(),
+        )
+        .unwrap();
+        assert!(result.is_empty());
+
+        // Corrupt the magic of the embedded signature superblob
+        let mut corrupt = signed.clone();
+        let magic = 0xfade_0cc0u32.to_be_bytes();
+        let pos = corrupt.windows(4).rposition(|w| w == magic).unwrap();
+        corrupt[pos] = 0;
+        let result = diff(
+            &FatReader::new(&signed).unwrap(),
+            &FatReader::new(&corrupt).unwrap(),
+        )
+        .unwrap();
+        let slice = &result.changed[0];
+        assert!(slice.new_signature.as_ref().unwrap().error.is_some());
+        assert!(result.to_string().contains(" -> invalid ("));
+        let result = diff(
+            &FatReader::new(&corrupt).unwrap(),
+            &FatReader::new(&corrupt).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(result.changed.len(), 1);
+        assert!(result.changed[0].signature_changed());
+    }
+}
@@ -0,0 +1,322 @@
+//! Universal `.dSYM` bundles matching universal executables
+use std::{fs, io, path::Path};
+
+use goblin::mach::{header::MH_DSYM, Mach};
+use uuid::Uuid;
+
+use crate::{
+    bundle::prepare_output_dir, error::Error, info::SliceInfo, read::FatReader, write::FatWriter,
+    xcframework::escape,
+};
+
+/// Builder of a universal executable together with its universal `.dSYM` companion
+#[derive(Debug, Default)]
+pub struct DsymWriter {
+    binary: FatWriter,
+    dsym: FatWriter,
+    overwrite: bool,
+}
+
+impl DsymWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a thin or fat executable and its thin or fat dSYM companion file
+    ///
+    /// Every slice of the executable needs a dSYM slice with the same `LC_UUID`,
+    /// extra dSYM slices are ignored. Nothing is added on failure.
+    pub fn add<T: Into<Vec<u8>>, U: Into<Vec<u8>>>(
+        &mut self,
+        binary: T,
+        dsym: U,
+    ) -> Result<(), Error> {
+        let mut binary_fat = FatWriter::new();
+        binary_fat.add(binary)?;
+        let mut dsym_fat = FatWriter::new();
+        dsym_fat.add(dsym)?;
+        check_dsym(&binary_fat, &dsym_fat)?;
+        let mut binary = staged(&self.binary)?;
+        let mut dsym = staged(&self.dsym)?;
+        for (arch, data) in binary_fat.iter() {
+            let dsym_data = dsym_fat.get(arch).ok_or_else(|| Error::ArchMismatch {
+                expected: arch.to_string(),
+                found: dsym_fat.arches().join(", "),
+            })?;
+            binary.add(data.to_vec())?;
+            dsym.add(dsym_data.to_vec())?;
+        }
+        self.binary = binary;
+        self.dsym = dsym;
+        Ok(())
+    }
+
+    /// Replace an existing `.dSYM` bundle in [`DsymWriter::write_to_files`]
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    /// The universal executable
+    pub fn binary(&self) -> &FatWriter {
+        &self.binary
+    }
+
+    /// The universal dSYM companion file
+    pub fn dsym(&self) -> &FatWriter {
+        &self.dsym
+    }
+
+    /// Write the executable to `path` and the dSYM bundle next to it as `<path>.dSYM`
+    ///
+    /// An existing `.dSYM` bundle is only replaced when enabled with
+    /// [`DsymWriter::set_overwrite`].
+    pub fn write_to_files<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                Error::InvalidMachO(format!("invalid executable path {}", path.display()))
+            })?;
+        let bundle = path.with_file_name(format!("{}.dSYM", name));
+        if self.overwrite && bundle.exists() && !is_dsym_bundle(&bundle) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a dSYM bundle", bundle.display()),
+            )));
+        }
+        prepare_output_dir(&bundle, &[], self.overwrite)?;
+        self.binary.write_to_file(path)?;
+
+        let dwarf = bundle.join("Contents/Resources/DWARF");
+        fs::create_dir_all(&dwarf)?;
+        self.dsym.write_to_file(dwarf.join(name))?;
+        fs::write(bundle.join("Contents/Info.plist"), info_plist(name))?;
+        Ok(())
+    }
+}
+
+/// A copy of a writer to stage new slices in
+fn staged(fat: &FatWriter) -> Result<FatWriter, Error> {
+    let mut staged = FatWriter::new();
+    for (_, data) in fat.iter() {
+        staged.add(data.to_vec())?;
+    }
+    Ok(staged)
+}
+
+/// Whether a directory looks like a `.dSYM` bundle, going by its `Info.plist`
+fn is_dsym_bundle(path: &Path) -> bool {
+    fs::read_to_string(path.join("Contents/Info.plist"))
+        .is_ok_and(|plist| plist.contains("<string>dSYM</string>"))
+}
+
+/// Check that every slice of an executable has a dSYM slice with the same UUID
+pub fn check_dsym(binary: &FatWriter, dsym: &FatWriter) -> Result<(), Error> {
+    for (arch, data) in binary.iter() {
+        let dsym_data = dsym.get(arch).ok_or_else(|| Error::ArchMismatch {
+            expected: arch.to_string(),
+            found: dsym.arches().join(", "),
+        })?;
+        let dsym_info = SliceInfo::parse(0, 0, 0, 0, dsym_data)?;
+        if dsym_info.filetype != Some(MH_DSYM) {
+            return Err(Error::InvalidMachO(format!(
+                "{} slice is not a dSYM companion file",
+                arch
+            )));
+        }
+        let binary_uuid = uuid(arch, data)?;
+        let dsym_uuid = uuid(arch, dsym_data)?;
+        if binary_uuid != dsym_uuid {
+            return Err(Error::UuidMismatch {
+                arch: arch.to_string(),
+                expected: binary_uuid,
+                found: dsym_uuid,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Find the slice with the given UUID in a thin or fat dSYM companion file
+pub fn extract_dsym(buffer: &[u8], uuid: Uuid) -> Result<Option<&[u8]>, Error> {
+    match Mach::parse(buffer)? {
+        Mach::Fat(_) => {
+            let reader = FatReader::new(buffer)?;
+            Ok(reader
+                .find_by_uuid(uuid)?
+                .map(|slice| reader.slice_data(&slice)))
+        }
+        Mach::Binary(_) => {
+            let matches = SliceInfo::parse(0, 0, 0, 0, buffer)?.uuid == Some(uuid);
+            Ok(matches.then_some(buffer))
+        }
+    }
+}
+
+fn uuid(arch: &str, data: &[u8]) -> Result<Uuid, Error> {
+    SliceInfo::parse(0, 0, 0, 0, data)?
+        .uuid
+        .ok_or_else(|| Error::InvalidMachO(format!("{} slice has no LC_UUID", arch)))
+}
+
+fn info_plist(name: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>CFBundleDevelopmentRegion</key>
+	<string>English</string>
+	<key>CFBundleIdentifier</key>
+	<string>com.apple.xcode.dsym.{}</string>
+	<key>CFBundleInfoDictionaryVersion</key>
+	<string>6.0</string>
+	<key>CFBundlePackageType</key>
+	<string>dSYM</string>
+	<key>CFBundleSignature</key>
+	<string>????</string>
+	<key>CFBundleShortVersionString</key>
+	<string>1.0</string>
+	<key>CFBundleVersion</key>
+	<string>1</string>
+</dict>
+</plist>
+"#,
+        escape(name)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use goblin::mach::MachO;
+
+    use super::{extract_dsym, DsymWriter};
+    use crate::{error::Error, info::SliceInfo, read::FatReader, write::FatWriter};
+
+    #[test]
+    fn test_dsym_writer() {
+        let mut writer = DsymWriter::new();
+        writer
+            .add(
+                fs::read("tests/fixtures/thin_x86_64").unwrap(),
+                fs::read("tests/fixtures/thin_x86_64.dsym").unwrap(),
+            )
+            .unwrap();
+        let arm64_dsym = fs::read("tests/fixtures/thin_arm64.dsym").unwrap();
+        writer
+            .add(
+                fs::read("tests/fixtures/thin_arm64").unwrap(),
+                arm64_dsym.clone(),
+            )
+            .unwrap();
+        assert_eq!(writer.dsym().arches(), vec!["x86_64", "arm64"]);
+        let macho = MachO::parse(&arm64_dsym, 0).unwrap();
+        assert!(macho
+            .segments
+            .iter()
+            .any(|segment| segment.name().unwrap() == "__DWARF"));
+
+        writer.set_overwrite(true);
+        writer.write_to_files("tests/output/hello").unwrap();
+        let dsym = fs::read("tests/output/hello.dSYM/Contents/Resources/DWARF/hello").unwrap();
+        assert_eq!(FatReader::new(&dsym).unwrap().narches, 2);
+        assert!(
+            fs::read_to_string("tests/output/hello.dSYM/Contents/Info.plist")
+                .unwrap()
+                .contains("com.apple.xcode.dsym.hello")
+        );
+
+        let uuid = SliceInfo::parse(0, 0, 0, 0, &arm64_dsym)
+            .unwrap()
+            .uuid
+            .unwrap();
+        assert_eq!(
+            extract_dsym(&dsym, uuid).unwrap(),
+            Some(arm64_dsym.as_slice())
+        );
+        assert_eq!(extract_dsym(&dsym, uuid::Uuid::nil()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_dsym_writer_uuid_mismatch() {
+        let mut writer = DsymWriter::new();
+        let binary = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        // Not a dSYM companion file
+        assert!(writer.add(binary.clone(), binary.clone()).is_err());
+        assert!(matches!(
+            writer.add(
+                binary.clone(),
+                fs::read("tests/fixtures/thin_arm64.dsym").unwrap()
+            ),
+            Err(Error::ArchMismatch { .. })
+        ));
+
+        // dSYM of another arm64 binary
+        let binary = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let dsym = fs::read("tests/fixtures/thin_arm64_ios.dsym").unwrap();
+        let uuid = |data: &[u8]| SliceInfo::parse(0, 0, 0, 0, data).unwrap().uuid.unwrap();
+        let (binary_uuid, dsym_uuid) = (uuid(&binary), uuid(&dsym));
+        assert!(matches!(
+            writer.add(binary, dsym),
+            Err(Error::UuidMismatch { arch, expected, found })
+                if arch == "arm64" && expected == binary_uuid && found == dsym_uuid
+        ));
+        assert!(writer.binary().is_empty());
+
+        // Failing on the second slice of a fat binary
+        writer
+            .add(
+                fs::read("tests/fixtures/thin_arm64").unwrap(),
+                fs::read("tests/fixtures/thin_arm64.dsym").unwrap(),
+            )
+            .unwrap();
+        let mut binary = FatWriter::new();
+        let mut dsym = FatWriter::new();
+        for arch in ["x86_64", "arm64"] {
+            binary
+                .add(fs::read(format!("tests/fixtures/thin_{}", arch)).unwrap())
+                .unwrap();
+            dsym.add(fs::read(format!("tests/fixtures/thin_{}.dsym", arch)).unwrap())
+                .unwrap();
+        }
+        let (mut binary_out, mut dsym_out) = (Vec::new(), Vec::new());
+        binary.write_to(&mut binary_out).unwrap();
+        dsym.write_to(&mut dsym_out).unwrap();
+        assert!(matches!(
+            writer.add(binary_out, dsym_out),
+            Err(Error::DuplicatedArch(arch)) if arch == "arm64"
+        ));
+        assert_eq!(writer.binary().arches(), vec!["arm64"]);
+        assert_eq!(writer.dsym().arches(), vec!["arm64"]);
+    }
+
+    #[test]
+    fn test_dsym_writer_existing_bundle() {
+        let mut writer = DsymWriter::new();
+        writer
+            .add(
+                fs::read("tests/fixtures/thin_arm64").unwrap(),
+                fs::read("tests/fixtures/thin_arm64.dsym").unwrap(),
+            )
+            .unwrap();
+        let bundle = Path::new("tests/output/existing.dSYM");
+        if bundle.exists() {
+            fs::remove_dir_all(bundle).unwrap();
+        }
+        writer.write_to_files("tests/output/existing").unwrap();
+        assert!(writer.write_to_files("tests/output/existing").is_err());
+        writer.set_overwrite(true);
+        writer.write_to_files("tests/output/existing").unwrap();
+
+        // Only dSYM bundles are replaced
+        let other = Path::new("tests/output/other.dSYM");
+        let _ = fs::remove_dir_all(other);
+        fs::create_dir_all(other).unwrap();
+        fs::write(other.join("keep"), "").unwrap();
+        assert!(writer.write_to_files("tests/output/other").is_err());
+        assert!(other.join("keep").exists());
+    }
+}
@@ -1,14 +1,49 @@
 use std::{error, fmt, io};
 
+#[cfg(feature = "codesign")]
+use crate::codesign::CodeSignatureError;
+use crate::info::Platform;
+use uuid::Uuid;
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Io(io::Error),
     Goblin(goblin::error::Error),
     NotFatBinary,
     InvalidMachO(String),
     DuplicatedArch(String),
+    ArchMismatch {
+        expected: String,
+        found: String,
+    },
+    UnsupportedTriple(String),
+    /// A slice was built for a platform that conflicts with an existing slice
+    IncompatiblePlatform {
+        arch: String,
+        platform: Platform,
+        existing_arch: String,
+        existing_platform: Platform,
+    },
+    /// Alignment of a slice that isn't a power of two
+    InvalidAlignment {
+        arch: String,
+        align: u64,
+    },
+    /// A dSYM slice doesn't belong to the executable slice of the same architecture
+    UuidMismatch {
+        arch: String,
+        expected: Uuid,
+        found: Uuid,
+    },
     #[cfg(feature = "bitcode")]
     Bitcode(llvm_bitcode::read::Error),
+    #[cfg(feature = "codesign")]
+    CodeSignature(CodeSignatureError),
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+    #[cfg(feature = "toml")]
+    Toml(toml::de::Error),
 }
 
 impl fmt::Display for Error {
@@ -19,8 +54,40 @@ impl fmt::Display for Error {
             Error::NotFatBinary => write!(f, "input is not a valid Mach-O fat binary"),
             Error::InvalidMachO(err) => write!(f, "{}", err),
             Error::DuplicatedArch(arch) => write!(f, "duplicated architecture {}", arch),
+            Error::UnsupportedTriple(triple) => write!(f, "unsupported target triple {}", triple),
+            Error::ArchMismatch { expected, found } => {
+                write!(f, "expected architecture {}, found {}", expected, found)
+            }
+            Error::IncompatiblePlatform {
+                arch,
+                platform,
+                existing_arch,
+                existing_platform,
+            } => write!(
+                f,
+                "{} slice for {} conflicts with {} slice for {}",
+                arch, platform, existing_arch, existing_platform
+            ),
+            Error::InvalidAlignment { arch, align } => {
+                write!(f, "alignment {} of {} is not a power of two", align, arch)
+            }
+            Error::UuidMismatch {
+                arch,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} dSYM UUID {} doesn't match the executable UUID {}",
+                arch, found, expected
+            ),
             #[cfg(feature = "bitcode")]
             Error::Bitcode(err) => err.fmt(f),
+            #[cfg(feature = "codesign")]
+            Error::CodeSignature(err) => err.fmt(f),
+            #[cfg(feature = "serde")]
+            Error::Json(err) => err.fmt(f),
+            #[cfg(feature = "toml")]
+            Error::Toml(err) => err.fmt(f),
         }
     }
 }
@@ -33,8 +100,19 @@ impl error::Error for Error {
             Error::NotFatBinary => None,
             Error::InvalidMachO(_) => None,
             Error::DuplicatedArch(_) => None,
+            Error::ArchMismatch { .. } => None,
+            Error::UnsupportedTriple(_) => None,
+            Error::IncompatiblePlatform { .. } => None,
+            Error::InvalidAlignment { .. } => None,
+            Error::UuidMismatch { .. } => None,
             #[cfg(feature = "bitcode")]
             Error::Bitcode(err) => Some(err),
+            #[cfg(feature = "codesign")]
+            Error::CodeSignature(err) => Some(err),
+            #[cfg(feature = "serde")]
+            Error::Json(err) => Some(err),
+            #[cfg(feature = "toml")]
+            Error::Toml(err) => Some(err),
         }
     }
 }
@@ -57,3 +135,24 @@ impl From<llvm_bitcode::read::Error> for Error {
         Self::Bitcode(err)
     }
 }
+
+#[cfg(feature = "codesign")]
+impl From<CodeSignatureError> for Error {
+    fn from(err: CodeSignatureError) -> Self {
+        Self::CodeSignature(err)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+#[cfg(feature = "toml")]
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Toml(err)
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+
+use goblin::{
+    mach::{
+        load_command::{
+            CommandVariant, PLATFORM_BRIDGEOS, PLATFORM_DRIVERKIT, PLATFORM_IOS,
+            PLATFORM_IOSSIMULATOR, PLATFORM_MACCATALYST, PLATFORM_MACOS, PLATFORM_TVOS,
+            PLATFORM_TVOSSIMULATOR, PLATFORM_VISIONOS, PLATFORM_VISIONOSSIMULATOR,
+            PLATFORM_WATCHOS, PLATFORM_WATCHOSSIMULATOR,
+        },
+        Mach, MachO,
+    },
+    Object,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    cputype::arch_name,
+    error::Error,
+    write::{LLVM_BITCODE_MAGIC, LLVM_BITCODE_WRAPPER_MAGIC},
+};
+
+/// Apple platform a Mach-O binary was built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Platform {
+    MacOS,
+    IOS,
+    TvOS,
+    WatchOS,
+    BridgeOS,
+    MacCatalyst,
+    IOSSimulator,
+    TvOSSimulator,
+    WatchOSSimulator,
+    DriverKit,
+    VisionOS,
+    VisionOSSimulator,
+    Unknown(u32),
+}
+
+impl Platform {
+    /// Convert from a `LC_BUILD_VERSION` platform number
+    pub fn from_raw(platform: u32) -> Self {
+        match platform {
+            PLATFORM_MACOS => Platform::MacOS,
+            PLATFORM_IOS => Platform::IOS,
+            PLATFORM_TVOS => Platform::TvOS,
+            PLATFORM_WATCHOS => Platform::WatchOS,
+            PLATFORM_BRIDGEOS => Platform::BridgeOS,
+            PLATFORM_MACCATALYST => Platform::MacCatalyst,
+            PLATFORM_IOSSIMULATOR => Platform::IOSSimulator,
+            PLATFORM_TVOSSIMULATOR => Platform::TvOSSimulator,
+            PLATFORM_WATCHOSSIMULATOR => Platform::WatchOSSimulator,
+            PLATFORM_DRIVERKIT => Platform::DriverKit,
+            PLATFORM_VISIONOS => Platform::VisionOS,
+            PLATFORM_VISIONOSSIMULATOR => Platform::VisionOSSimulator,
+            other => Platform::Unknown(other),
+        }
+    }
+
+    /// Name of the platform as used in target triples and by Xcode
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::MacOS => "macos",
+            Platform::IOS => "ios",
+            Platform::TvOS => "tvos",
+            Platform::WatchOS => "watchos",
+            Platform::BridgeOS => "bridgeos",
+            Platform::MacCatalyst => "maccatalyst",
+            Platform::IOSSimulator => "ios-simulator",
+            Platform::TvOSSimulator => "tvos-simulator",
+            Platform::WatchOSSimulator => "watchos-simulator",
+            Platform::DriverKit => "driverkit",
+            Platform::VisionOS => "visionos",
+            Platform::VisionOSSimulator => "visionos-simulator",
+            Platform::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Unknown(platform) => write!(f, "unknown({})", platform),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+/// An `X.Y.Z` version encoded in nibbles `xxxx.yy.zz`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Version {
+    pub major: u16,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl Version {
+    pub fn from_raw(version: u32) -> Self {
+        Self {
+            major: (version >> 16) as u16,
+            minor: (version >> 8) as u8,
+            patch: version as u8,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.patch != 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Kind of payload stored in a slice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PayloadKind {
+    /// Thin Mach-O binary
+    MachO,
+    /// Static library
+    Archive,
+    /// LLVM bitcode
+    Bitcode,
+    Unknown,
+}
+
+/// Metadata of a single slice of a fat binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SliceInfo {
+    pub arch: String,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub offset: u64,
+    pub size: u64,
+    /// Alignment as a power of 2
+    pub align: u32,
+    pub kind: PayloadKind,
+    /// Mach-O file type, only set for Mach-O payloads
+    pub filetype: Option<u32>,
+    pub uuid: Option<Uuid>,
+    pub platform: Option<Platform>,
+    pub min_os: Option<Version>,
+    /// Whether the slice has a `LC_CODE_SIGNATURE` load command
+    pub signed: bool,
+}
+
+impl SliceInfo {
+    pub(crate) fn parse(
+        cputype: u32,
+        cpusubtype: u32,
+        offset: u64,
+        align: u32,
+        data: &[u8],
+    ) -> Result<Self, Error> {
+        let mut info = SliceInfo {
+            arch: arch_name(cputype, cpusubtype)
+                .unwrap_or("unknown")
+                .to_string(),
+            cputype,
+            cpusubtype,
+            offset,
+            size: data.len() as u64,
+            align,
+            kind: PayloadKind::Unknown,
+            filetype: None,
+            uuid: None,
+            platform: None,
+            min_os: None,
+            signed: false,
+        };
+        match Object::parse(data)? {
+            Object::Mach(Mach::Binary(obj)) => {
+                info.kind = PayloadKind::MachO;
+                info.filetype = Some(obj.header.filetype);
+                info.read_load_commands(&obj);
+            }
+            Object::Archive(archive) => {
+                info.kind = PayloadKind::Archive;
+                // Static libraries have no version of their own, use the first object's
+                for member in archive.members() {
+                    let bytes = archive.extract(member, data)?;
+                    if let Ok(Object::Mach(Mach::Binary(obj))) = Object::parse(bytes) {
+                        info.read_load_commands(&obj);
+                        info.uuid = None;
+                        info.signed = false;
+                        if info.platform.is_some() {
+                            break;
+                        }
+                    }
+                }
+            }
+            _ => {
+                let magic = data
+                    .get(..4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+                if matches!(magic, Some(LLVM_BITCODE_WRAPPER_MAGIC | LLVM_BITCODE_MAGIC)) {
+                    info.kind = PayloadKind::Bitcode;
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    /// UUIDs of a Mach-O binary, or of every Mach-O member of a static library
+    pub(crate) fn uuids(data: &[u8]) -> Result<Vec<Uuid>, Error> {
+        let uuid = |obj: &MachO| {
+            obj.load_commands.iter().find_map(|lc| match &lc.command {
+                CommandVariant::Uuid(cmd) => Some(Uuid::from_bytes(cmd.uuid)),
+                _ => None,
+            })
+        };
+        let mut uuids = Vec::new();
+        match Object::parse(data)? {
+            Object::Mach(Mach::Binary(obj)) => uuids.extend(uuid(&obj)),
+            Object::Archive(archive) => {
+                for member in archive.members() {
+                    let bytes = archive.extract(member, data)?;
+                    if let Ok(Object::Mach(Mach::Binary(obj))) = Object::parse(bytes) {
+                        uuids.extend(uuid(&obj));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(uuids)
+    }
+
+    fn read_load_commands(&mut self, obj: &MachO) {
+        for lc in &obj.load_commands {
+            match &lc.command {
+                CommandVariant::Uuid(cmd) => self.uuid = Some(Uuid::from_bytes(cmd.uuid)),
+                CommandVariant::BuildVersion(cmd) => {
+                    self.platform = Some(Platform::from_raw(cmd.platform));
+                    self.min_os = Some(Version::from_raw(cmd.minos));
+                }
+                CommandVariant::VersionMinMacosx(cmd) => {
+                    self.platform = Some(Platform::MacOS);
+                    self.min_os = Some(Version::from_raw(cmd.version));
+                }
+                CommandVariant::VersionMinIphoneos(cmd) => {
+                    self.platform = Some(Platform::IOS);
+                    self.min_os = Some(Version::from_raw(cmd.version));
+                }
+                CommandVariant::VersionMinTvos(cmd) => {
+                    self.platform = Some(Platform::TvOS);
+                    self.min_os = Some(Version::from_raw(cmd.version));
+                }
+                CommandVariant::VersionMinWatchos(cmd) => {
+                    self.platform = Some(Platform::WatchOS);
+                    self.min_os = Some(Version::from_raw(cmd.version));
+                }
+                CommandVariant::CodeSignature(_) => self.signed = true,
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Planned layout of a fat binary, see [`FatWriter::plan`](crate::FatWriter::plan)
+use goblin::mach::{
+    cputype::{CpuSubType, CpuType},
+    fat::FAT_MAGIC,
+};
+
+use crate::manifest::FAT_MAGIC_64;
+
+/// Location of a slice in a [`Layout`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceLayout {
+    pub arch: &'static str,
+    pub cpu_type: CpuType,
+    pub cpu_subtype: CpuSubType,
+    pub offset: u64,
+    pub size: u64,
+    /// Alignment in bytes
+    pub align: u64,
+    /// Zero bytes written between the end of the previous slice, or the header, and this slice
+    pub padding: u64,
+}
+
+/// Exact layout of a fat binary as written by [`FatWriter`](crate::FatWriter)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Whether the fat64 format is used
+    pub fat64: bool,
+    /// Size of the fat header and fat arch entries
+    pub header_size: u64,
+    /// Slices in file order
+    pub slices: Vec<SliceLayout>,
+    /// Size of the whole file
+    pub total_size: u64,
+}
+
+impl Layout {
+    /// The fat header followed by the fat arch entries, empty if there are no slices
+    ///
+    /// Note that the fat binary header is big-endian, regardless of the
+    /// endianness of the contained files.
+    pub fn header(&self) -> Vec<u8> {
+        if self.slices.is_empty() {
+            return Vec::new();
+        }
+        let mut hdr = Vec::with_capacity(self.header_size as usize / 4);
+        hdr.push(if self.fat64 { FAT_MAGIC_64 } else { FAT_MAGIC });
+        hdr.push(self.slices.len() as u32);
+        for slice in &self.slices {
+            hdr.push(slice.cpu_type);
+            hdr.push(slice.cpu_subtype);
+            if self.fat64 {
+                hdr.push((slice.offset >> 32) as u32);
+            }
+            hdr.push(slice.offset as u32);
+            if self.fat64 {
+                hdr.push((slice.size >> 32) as u32);
+            }
+            hdr.push(slice.size as u32);
+            hdr.push(slice.align.trailing_zeros());
+            if self.fat64 {
+                // Reserved
+                hdr.push(0);
+            }
+        }
+        hdr.iter().flat_map(|field| field.to_be_bytes()).collect()
+    }
+}
@@ -1,7 +1,47 @@
+#[cfg(feature = "bitcode")]
+mod bitcode;
+mod bundle;
+#[cfg(feature = "codesign")]
+mod codesign;
+mod cputype;
+mod diff;
+mod dsym;
 mod error;
+mod info;
+mod layout;
+mod macho;
+mod manifest;
 mod read;
+mod strip;
+mod triple;
 mod write;
+mod xcframework;
 
+#[cfg(feature = "bitcode")]
+pub use self::bitcode::BitcodeWrapper;
+#[cfg(feature = "codesign")]
+pub use self::codesign::{
+    adhoc_sign, code_signature, CodeDirectory, CodeSignature, CodeSignatureError, HashType,
+    SigningOptions,
+};
+
+pub use self::bundle::{
+    sniff, Bundle, BundleDifference, BundleFile, BundleOperation, MachOKind, MergeReport,
+    ProcessedFile,
+};
+pub use self::cputype::{
+    register_cpu_type, Arch, CustomCpuType, PtrAuthAbi, CPU_SUBTYPE_PTRAUTH_ABI,
+    CPU_SUBTYPE_PTRAUTH_KERNEL_ABI, DEFAULT_ALIGN,
+};
+pub use self::diff::{diff, FatDiff, SignatureIdentity, SliceDiff};
+pub use self::dsym::{check_dsym, extract_dsym, DsymWriter};
 pub use self::error::Error;
-pub use self::read::FatReader;
+pub use self::info::{PayloadKind, Platform, SliceInfo, Version};
+pub use self::layout::{Layout, SliceLayout};
+pub use self::manifest::{BuildManifest, BuildSlice, FatArchEntry, FatHeader, FatManifest};
+pub use self::read::{FatArch, FatReader};
+pub use self::strip::{has_embedded_bitcode, remove_code_signature, remove_embedded_bitcode};
+pub use self::triple::{get_arch_from_triple, Triple};
 pub use self::write::FatWriter;
+pub use self::xcframework::{XcframeworkBuilder, XcframeworkLibrary};
+pub use uuid::Uuid;
@@ -0,0 +1,206 @@
+//! Helpers for inspecting and patching the load commands of thin Mach-O binaries in place
+use goblin::mach::{
+    header::{SIZEOF_HEADER_32, SIZEOF_HEADER_64},
+    load_command::{CommandVariant, LoadCommand},
+    MachO,
+};
+
+use crate::error::Error;
+
+/// A segment load command located in a thin Mach-O binary
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SegmentInfo {
+    /// Offset of the load command in the file
+    pub cmd_offset: usize,
+    pub is_64: bool,
+    pub nsects: u32,
+    pub fileoff: u64,
+    pub filesize: u64,
+    pub vmaddr: u64,
+    pub vmsize: u64,
+}
+
+impl SegmentInfo {
+    pub fn set_vmaddr(&self, buf: &mut [u8], vmaddr: u64) {
+        if self.is_64 {
+            write_u64(buf, self.cmd_offset + 24, vmaddr);
+        } else {
+            write_u32(buf, self.cmd_offset + 24, vmaddr as u32);
+        }
+    }
+
+    pub fn set_vmsize(&self, buf: &mut [u8], vmsize: u64) {
+        if self.is_64 {
+            write_u64(buf, self.cmd_offset + 32, vmsize);
+        } else {
+            write_u32(buf, self.cmd_offset + 28, vmsize as u32);
+        }
+    }
+
+    pub fn set_fileoff(&self, buf: &mut [u8], fileoff: u64) {
+        if self.is_64 {
+            write_u64(buf, self.cmd_offset + 40, fileoff);
+        } else {
+            write_u32(buf, self.cmd_offset + 32, fileoff as u32);
+        }
+    }
+
+    pub fn set_filesize(&self, buf: &mut [u8], filesize: u64) {
+        if self.is_64 {
+            write_u64(buf, self.cmd_offset + 48, filesize);
+        } else {
+            write_u32(buf, self.cmd_offset + 36, filesize as u32);
+        }
+    }
+}
+
+/// Load command layout of a thin, little-endian Mach-O binary
+#[derive(Debug)]
+pub(crate) struct MachLayout {
+    pub is_64: bool,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    #[cfg_attr(not(feature = "codesign"), allow(dead_code))]
+    pub filetype: u32,
+    pub sizeofcmds: u32,
+    pub load_commands: Vec<LoadCommand>,
+    /// Lowest file offset of any section contents, load commands can't grow past it
+    #[cfg_attr(not(feature = "codesign"), allow(dead_code))]
+    pub first_section_offset: usize,
+}
+
+impl MachLayout {
+    pub fn parse(buffer: &[u8]) -> Result<Self, Error> {
+        let mach = MachO::parse(buffer, 0)?;
+        if !mach.little_endian {
+            return Err(Error::InvalidMachO(
+                "big-endian Mach-O files are unsupported".to_string(),
+            ));
+        }
+        let mut first_section_offset = buffer.len();
+        for segment in &mach.segments {
+            for (section, _) in segment.sections()? {
+                if section.offset != 0 && section.size != 0 {
+                    first_section_offset = first_section_offset.min(section.offset as usize);
+                }
+            }
+        }
+        Ok(Self {
+            is_64: mach.is_64,
+            cputype: mach.header.cputype,
+            cpusubtype: mach.header.cpusubtype,
+            filetype: mach.header.filetype,
+            sizeofcmds: mach.header.sizeofcmds,
+            load_commands: mach.load_commands,
+            first_section_offset,
+        })
+    }
+
+    pub fn header_size(&self) -> usize {
+        if self.is_64 {
+            SIZEOF_HEADER_64
+        } else {
+            SIZEOF_HEADER_32
+        }
+    }
+
+    /// File offset right after the last load command
+    pub fn load_commands_end(&self) -> usize {
+        self.header_size() + self.sizeofcmds as usize
+    }
+
+    pub fn segment(&self, name: &str) -> Option<SegmentInfo> {
+        self.segments()
+            .find(|(segment_name, _)| *segment_name == name)
+            .map(|(_, segment)| segment)
+    }
+
+    /// Segment load commands with their names, in load command order
+    pub fn segments(&self) -> impl Iterator<Item = (&str, SegmentInfo)> {
+        self.load_commands
+            .iter()
+            .filter_map(|lc| match &lc.command {
+                CommandVariant::Segment64(seg) => Some((
+                    segname(&seg.segname),
+                    SegmentInfo {
+                        cmd_offset: lc.offset,
+                        is_64: true,
+                        nsects: seg.nsects,
+                        fileoff: seg.fileoff,
+                        filesize: seg.filesize,
+                        vmaddr: seg.vmaddr,
+                        vmsize: seg.vmsize,
+                    },
+                )),
+                CommandVariant::Segment32(seg) => Some((
+                    segname(&seg.segname),
+                    SegmentInfo {
+                        cmd_offset: lc.offset,
+                        is_64: false,
+                        nsects: seg.nsects,
+                        fileoff: seg.fileoff as u64,
+                        filesize: seg.filesize as u64,
+                        vmaddr: seg.vmaddr as u64,
+                        vmsize: seg.vmsize as u64,
+                    },
+                )),
+                _ => None,
+            })
+    }
+
+    /// Append a load command, failing if there is no room before the first section
+    #[cfg_attr(not(feature = "codesign"), allow(dead_code))]
+    pub fn add_load_command(&mut self, buf: &mut [u8], command: &[u8]) -> Result<usize, Error> {
+        let offset = self.load_commands_end();
+        if offset + command.len() > self.first_section_offset {
+            return Err(Error::InvalidMachO(
+                "not enough space to add a load command".to_string(),
+            ));
+        }
+        buf[offset..offset + command.len()].copy_from_slice(command);
+        let ncmds = read_u32(buf, 16);
+        write_u32(buf, 16, ncmds + 1);
+        self.sizeofcmds += command.len() as u32;
+        write_u32(buf, 20, self.sizeofcmds);
+        Ok(offset)
+    }
+
+    /// Remove the load command at `offset`, shifting the following ones down
+    pub fn remove_load_command(&mut self, buf: &mut [u8], offset: usize) {
+        let end = self.load_commands_end();
+        let cmdsize = read_u32(buf, offset + 4) as usize;
+        buf.copy_within(offset + cmdsize..end, offset);
+        buf[end - cmdsize..end].fill(0);
+        let ncmds = read_u32(buf, 16);
+        write_u32(buf, 16, ncmds - 1);
+        self.sizeofcmds -= cmdsize as u32;
+        write_u32(buf, 20, self.sizeofcmds);
+        // Keep the offsets of the remaining load commands in sync
+        self.load_commands.retain(|lc| lc.offset != offset);
+        for lc in &mut self.load_commands {
+            if lc.offset > offset {
+                lc.offset -= cmdsize;
+            }
+        }
+    }
+}
+
+pub(crate) fn segname(name: &[u8; 16]) -> &str {
+    let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    std::str::from_utf8(&name[..end]).unwrap_or_default()
+}
+
+#[inline]
+pub(crate) fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+pub(crate) fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[inline]
+pub(crate) fn write_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
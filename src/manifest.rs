@@ -0,0 +1,328 @@
+//! Fat header manifests describing existing fat binaries and fat binaries to build
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use goblin::mach::fat::FAT_MAGIC;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cputype::{arch_from_name, arch_name, Arch},
+    error::Error,
+    info::{PayloadKind, SliceInfo},
+    read::FatReader,
+    write::FatWriter,
+};
+
+/// Magic number of fat binaries with 64-bit offsets and sizes
+pub(crate) const FAT_MAGIC_64: u32 = FAT_MAGIC + 1;
+
+/// Header of a fat binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FatHeader {
+    pub magic: u32,
+    /// Whether the fat arch entries use 64-bit offsets and sizes
+    pub fat64: bool,
+    pub nfat_arch: u32,
+}
+
+impl FatHeader {
+    pub fn new(nfat_arch: u32, fat64: bool) -> Self {
+        Self {
+            magic: if fat64 { FAT_MAGIC_64 } else { FAT_MAGIC },
+            fat64,
+            nfat_arch,
+        }
+    }
+}
+
+/// A fat arch entry describing the location of a slice
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FatArchEntry {
+    pub arch: String,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub offset: u64,
+    pub size: u64,
+    /// Alignment as a power of 2
+    pub align: u32,
+    pub kind: PayloadKind,
+}
+
+impl From<&SliceInfo> for FatArchEntry {
+    fn from(info: &SliceInfo) -> Self {
+        Self {
+            arch: info.arch.clone(),
+            cputype: info.cputype,
+            cpusubtype: info.cpusubtype,
+            offset: info.offset,
+            size: info.size,
+            align: info.align,
+            kind: info.kind,
+        }
+    }
+}
+
+/// Complete description of a fat binary container
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FatManifest {
+    pub header: FatHeader,
+    /// Fat arch entries in file order
+    pub arches: Vec<FatArchEntry>,
+}
+
+impl FatManifest {
+    /// Parse a manifest from the JSON produced by [`FatReader::to_manifest`]
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Rebuild a fat binary with this layout from the slices of `source`
+    ///
+    /// Slices are looked up in `source` by cputype and cpusubtype and laid out in
+    /// the listed order at the listed offsets and alignments, so a manifest from
+    /// [`FatReader::manifest`] can be edited and rebuilt. Sizes and payload kinds
+    /// are taken from `source`.
+    pub fn build(&self, source: &[u8]) -> Result<FatWriter, Error> {
+        let reader = FatReader::new(source)?;
+        let mut fat = FatWriter::new();
+        fat.set_explicit_layout(true);
+        fat.set_fat64(self.header.fat64);
+        for entry in &self.arches {
+            let arch = Arch::new(entry.cputype, entry.cpusubtype);
+            let data = reader.extract_arch(arch).ok_or_else(|| {
+                Error::InvalidMachO(format!("no {} slice in the source fat binary", entry.arch))
+            })?;
+            let align = 1u32.checked_shl(entry.align).ok_or_else(|| {
+                Error::InvalidMachO(format!("alignment 2^{} is too large", entry.align))
+            })?;
+            fat.add_raw(arch, align, data)?;
+            let name = arch_name(entry.cputype, entry.cpusubtype).unwrap_or("unknown");
+            fat.set_offset(name, entry.offset);
+        }
+        Ok(fat)
+    }
+}
+
+/// A slice file listed in a [`BuildManifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BuildSlice {
+    /// Path of the thin binary, relative paths are resolved against the base directory
+    pub path: PathBuf,
+    /// Architecture the slice is expected to contain
+    pub arch: String,
+    /// Alignment as a power of 2, defaults to the alignment of the architecture
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub align: Option<u32>,
+    /// Offset in the fat binary, defaults to the next aligned offset
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub offset: Option<u64>,
+}
+
+/// Description of a fat binary to rebuild from thin binaries
+///
+/// Slices are laid out in the listed order, each aligned to its own alignment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BuildManifest {
+    /// Whether to use the fat64 format
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fat64: bool,
+    pub slices: Vec<BuildSlice>,
+}
+
+impl BuildManifest {
+    /// Parse a manifest from JSON
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Parse a manifest from TOML
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Read the slice files and lay them out as described
+    ///
+    /// Fails if a slice file doesn't contain exactly the stated architecture.
+    pub fn build<P: AsRef<Path>>(&self, base_dir: P) -> Result<FatWriter, Error> {
+        let base_dir = base_dir.as_ref();
+        let mut fat = FatWriter::new();
+        fat.set_explicit_layout(true);
+        fat.set_fat64(self.fat64);
+        for slice in &self.slices {
+            let expected = arch_from_name(&slice.arch)
+                .and_then(|(cpu_type, cpu_subtype)| arch_name(cpu_type, cpu_subtype))
+                .ok_or_else(|| {
+                    Error::InvalidMachO(format!("unknown architecture {}", slice.arch))
+                })?;
+            let data = fs::read(base_dir.join(&slice.path))?;
+            let index = fat.len();
+            fat.add(data)?;
+            let arches = fat.arches();
+            if arches.len() != index + 1 || arches[index] != expected {
+                return Err(Error::ArchMismatch {
+                    expected: slice.arch.clone(),
+                    found: arches[index..].join(", "),
+                });
+            }
+            if let Some(align) = slice.align {
+                let align = 1u32.checked_shl(align).ok_or_else(|| {
+                    Error::InvalidMachO(format!("alignment 2^{} is too large", align))
+                })?;
+                fat.set_align(expected, align)?;
+            }
+            if let Some(offset) = slice.offset {
+                fat.set_offset(expected, offset);
+            }
+        }
+        Ok(fat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{BuildManifest, BuildSlice};
+    use crate::{cputype::Arch, error::Error, read::FatReader};
+
+    fn manifest(x86_64_arch: &str) -> BuildManifest {
+        BuildManifest {
+            fat64: false,
+            slices: vec![
+                BuildSlice {
+                    path: "thin_x86_64".into(),
+                    arch: x86_64_arch.to_string(),
+                    align: Some(12),
+                    offset: None,
+                },
+                BuildSlice {
+                    path: "thin_arm64".into(),
+                    arch: "arm64".to_string(),
+                    align: None,
+                    offset: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_build_manifest() {
+        let fat = manifest("x86_64").build("tests/fixtures").unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let arches = reader.manifest().unwrap().arches;
+        assert_eq!(arches[0].arch, "x86_64");
+        assert_eq!((arches[0].offset, arches[0].align), (0x1000, 12));
+        assert_eq!(arches[1].arch, "arm64");
+        assert_eq!((arches[1].offset, arches[1].align), (0x8000, 14));
+    }
+
+    #[test]
+    fn test_build_manifest_offset() {
+        let mut manifest = manifest("x86_64");
+        manifest.slices[1].offset = Some(0x10000);
+        let fat = manifest.build("tests/fixtures").unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let arches = FatReader::new(&out).unwrap().manifest().unwrap().arches;
+        assert_eq!((arches[1].offset, arches[1].align), (0x10000, 14));
+
+        // Overlapping the previous slice
+        manifest.slices[1].offset = Some(0x4000);
+        let mut out = Vec::new();
+        let fat = manifest.build("tests/fixtures").unwrap();
+        assert!(fat.write_to(&mut out).is_err());
+    }
+
+    #[test]
+    fn test_fat_manifest_round_trip() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let manifest = FatReader::new(&buf).unwrap().manifest().unwrap();
+        let mut out = Vec::new();
+        manifest.build(&buf).unwrap().write_to(&mut out).unwrap();
+        assert_eq!(out, buf);
+
+        // Move arm64 first and spread the slices out
+        let mut edited = manifest.clone();
+        edited.arches.rotate_right(1);
+        let mut offset = 0x10000;
+        for entry in &mut edited.arches {
+            entry.align = 16;
+            entry.offset = offset;
+            offset += entry.size.div_ceil(0x10000) * 0x10000;
+        }
+        let mut out = Vec::new();
+        edited.build(&buf).unwrap().write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.manifest().unwrap(), edited);
+        let original = FatReader::new(&buf).unwrap();
+        for entry in &manifest.arches {
+            let arch = Arch::new(entry.cputype, entry.cpusubtype);
+            assert_eq!(reader.extract_arch(arch), original.extract_arch(arch));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_fat_manifest_json_round_trip() {
+        use super::FatManifest;
+
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let json = FatReader::new(&buf).unwrap().to_manifest().unwrap();
+        let manifest = FatManifest::from_json(&json).unwrap();
+        let mut out = Vec::new();
+        manifest.build(&buf).unwrap().write_to(&mut out).unwrap();
+        assert_eq!(out, buf);
+    }
+
+    #[test]
+    fn test_build_manifest_arch_mismatch() {
+        let err = manifest("arm64").build("tests/fixtures").unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ArchMismatch { expected, found } if expected == "arm64" && found == "x86_64"
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_build_manifest_from_json() {
+        let json = r#"{
+            "slices": [
+                {"path": "thin_x86_64", "arch": "x86_64", "align": 12},
+                {"path": "thin_arm64", "arch": "arm64"}
+            ]
+        }"#;
+        assert_eq!(BuildManifest::from_json(json).unwrap(), manifest("x86_64"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_build_manifest_from_toml() {
+        let toml = r#"
+            fat64 = false
+
+            [[slices]]
+            path = "thin_x86_64"
+            arch = "x86_64"
+            align = 12
+
+            [[slices]]
+            path = "thin_arm64"
+            arch = "arm64"
+        "#;
+        assert_eq!(BuildManifest::from_toml(toml).unwrap(), manifest("x86_64"));
+    }
+}
@@ -1,47 +1,203 @@
-use goblin::mach::{cputype::get_arch_from_flag, Mach, MultiArch};
+use goblin::mach::Mach;
+#[cfg(feature = "codesign")]
+use goblin::Object;
+use uuid::Uuid;
 
+#[cfg(feature = "codesign")]
+use crate::codesign::{code_signature, CodeSignature};
+use crate::cputype::{arch_name, is_arch, Arch};
 use crate::error::Error;
+use crate::info::SliceInfo;
+use crate::manifest::{FatArchEntry, FatHeader, FatManifest, FAT_MAGIC_64};
 
-/// Mach-O fat binary reader
+/// A fat arch entry, with 64-bit offsets and sizes for both the fat and fat64 formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatArch {
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub offset: u64,
+    pub size: u64,
+    /// Alignment as a power of 2
+    pub align: u32,
+}
+
+impl FatArch {
+    /// Contents of the slice in `bytes`, empty if it is out of bounds
+    pub fn slice<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        usize::try_from(self.offset)
+            .ok()
+            .zip(usize::try_from(self.size).ok())
+            .and_then(|(start, size)| bytes.get(start..start.checked_add(size)?))
+            .unwrap_or_default()
+    }
+}
+
+/// Mach-O fat binary reader, for both the fat and fat64 formats
 #[derive(Debug)]
 pub struct FatReader<'a> {
     buffer: &'a [u8],
-    fat: MultiArch<'a>,
+    fat64: bool,
+    arches: Vec<FatArch>,
+    /// Number of fat arch entries
+    pub narches: usize,
 }
 
 impl<'a> FatReader<'a> {
     /// Parse a Mach-O FAT binary from a buffer
     pub fn new(buffer: &'a [u8]) -> Result<Self, Error> {
-        match Mach::parse(buffer)? {
-            Mach::Fat(fat) => Ok(Self { buffer, fat }),
-            Mach::Binary(_) => Err(Error::NotFatBinary),
+        let magic = buffer.get(..4).map(|magic| magic.try_into().unwrap());
+        let fat64 = magic.map(u32::from_be_bytes) == Some(FAT_MAGIC_64);
+        if !fat64 {
+            if let Mach::Binary(_) = Mach::parse(buffer)? {
+                return Err(Error::NotFatBinary);
+            }
         }
+        let read_u32 = |offset: usize| {
+            buffer
+                .get(offset..offset + 4)
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| Error::InvalidMachO("fat header is truncated".to_string()))
+        };
+        let read_u64 = |offset: usize| -> Result<u64, Error> {
+            Ok((read_u32(offset)? as u64) << 32 | read_u32(offset + 4)? as u64)
+        };
+        let narches = read_u32(4)? as usize;
+        let sizeof_fat_arch = if fat64 { 32 } else { 20 };
+        if narches > buffer.len() / sizeof_fat_arch {
+            return Err(Error::InvalidMachO("fat header is truncated".to_string()));
+        }
+        let mut arches = Vec::with_capacity(narches);
+        for index in 0..narches {
+            let entry = 8 + index * sizeof_fat_arch;
+            arches.push(if fat64 {
+                FatArch {
+                    cputype: read_u32(entry)?,
+                    cpusubtype: read_u32(entry + 4)?,
+                    offset: read_u64(entry + 8)?,
+                    size: read_u64(entry + 16)?,
+                    align: read_u32(entry + 24)?,
+                }
+            } else {
+                FatArch {
+                    cputype: read_u32(entry)?,
+                    cpusubtype: read_u32(entry + 4)?,
+                    offset: read_u32(entry + 8)? as u64,
+                    size: read_u32(entry + 12)? as u64,
+                    align: read_u32(entry + 16)?,
+                }
+            });
+        }
+        Ok(Self {
+            buffer,
+            fat64,
+            arches,
+            narches,
+        })
+    }
+
+    /// Fat arch entries, in file order
+    pub fn iter_arches(&self) -> impl Iterator<Item = FatArch> + '_ {
+        self.arches.iter().copied()
     }
 
     /// Extract thin binary by arch name
     pub fn extract(&self, arch_name: &str) -> Option<&'a [u8]> {
-        if let Some((cpu_type, _cpu_subtype)) = get_arch_from_flag(arch_name) {
-            return self
-                .fat
-                .find_cputype(cpu_type)
-                .unwrap_or_default()
-                .map(|arch| arch.slice(self.buffer));
+        self.iter_arches()
+            .find(|arch| is_arch(arch_name, arch.cputype, arch.cpusubtype))
+            .map(|arch| arch.slice(self.buffer))
+    }
+
+    /// Extract the slice of an exact cputype and cpusubtype
+    pub fn extract_arch(&self, arch: Arch) -> Option<&'a [u8]> {
+        self.iter_arches()
+            .find(|fat_arch| {
+                fat_arch.cputype == arch.cpu_type && fat_arch.cpusubtype == arch.cpu_subtype
+            })
+            .map(|fat_arch| fat_arch.slice(self.buffer))
+    }
+
+    /// Metadata of every slice, in file order
+    pub fn slices(&self) -> Result<Vec<SliceInfo>, Error> {
+        let mut slices = Vec::with_capacity(self.narches);
+        for arch in self.iter_arches() {
+            slices.push(SliceInfo::parse(
+                arch.cputype,
+                arch.cpusubtype,
+                arch.offset,
+                arch.align,
+                arch.slice(self.buffer),
+            )?);
         }
-        None
+        Ok(slices)
     }
-}
 
-impl<'a> std::ops::Deref for FatReader<'a> {
-    type Target = MultiArch<'a>;
+    /// Header of the fat binary
+    pub fn header(&self) -> FatHeader {
+        FatHeader::new(self.narches as u32, self.fat64)
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.fat
+    /// Header and fat arch entries of the fat binary
+    pub fn manifest(&self) -> Result<FatManifest, Error> {
+        Ok(FatManifest {
+            header: self.header(),
+            arches: self.slices()?.iter().map(FatArchEntry::from).collect(),
+        })
+    }
+
+    /// Describe the fat binary as pretty printed JSON
+    #[cfg(feature = "serde")]
+    pub fn to_manifest(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(&self.manifest()?)?)
     }
-}
 
-impl<'a> std::ops::DerefMut for FatReader<'a> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.fat
+    /// Contents of the slice described by `info`
+    pub fn slice_data(&self, info: &SliceInfo) -> &'a [u8] {
+        let start = (info.offset as usize).min(self.buffer.len());
+        let end = (info.offset + info.size).min(self.buffer.len() as u64) as usize;
+        &self.buffer[start..end]
+    }
+
+    /// `(arch name, UUID)` pairs of every slice, in file order
+    ///
+    /// Static libraries report the UUIDs of their members.
+    pub fn uuids(&self) -> Result<Vec<(&'static str, Uuid)>, Error> {
+        let mut uuids = Vec::with_capacity(self.narches);
+        for arch in self.iter_arches() {
+            let name = arch_name(arch.cputype, arch.cpusubtype).unwrap_or("unknown");
+            for uuid in SliceInfo::uuids(arch.slice(self.buffer))? {
+                uuids.push((name, uuid));
+            }
+        }
+        Ok(uuids)
+    }
+
+    /// Find the slice with the given UUID, or the static library with a member of that UUID
+    pub fn find_by_uuid(&self, uuid: Uuid) -> Result<Option<SliceInfo>, Error> {
+        for slice in self.slices()? {
+            if SliceInfo::uuids(self.slice_data(&slice))?.contains(&uuid) {
+                return Ok(Some(slice));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parse the embedded code signature of every slice
+    ///
+    /// Returns `(arch name, signature)` pairs, the signature is `None` for unsigned
+    /// slices and slices that aren't Mach-O binaries such as static libraries.
+    #[cfg(feature = "codesign")]
+    pub fn code_signatures(&self) -> Result<Vec<(&'static str, Option<CodeSignature>)>, Error> {
+        let mut signatures = Vec::with_capacity(self.narches);
+        for arch in self.iter_arches() {
+            let name = arch_name(arch.cputype, arch.cpusubtype).unwrap_or("unknown");
+            let data = arch.slice(self.buffer);
+            let signature = match Object::parse(data)? {
+                Object::Mach(Mach::Binary(_)) => code_signature(data)?,
+                _ => None,
+            };
+            signatures.push((name, signature));
+        }
+        Ok(signatures)
     }
 }
 
@@ -127,4 +283,139 @@ mod test {
         let arm64_obj = Object::parse(arm64).unwrap();
         assert!(matches!(arm64_obj, Object::Archive(_)));
     }
+
+    #[test]
+    fn test_fat_reader_slices() {
+        use crate::info::{PayloadKind, Platform};
+
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let slices = reader.slices().unwrap();
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0].arch, "x86_64");
+        assert_eq!(slices[0].kind, PayloadKind::MachO);
+        assert_eq!(slices[0].platform, Some(Platform::MacOS));
+        assert!(slices[0].uuid.is_some());
+        assert_eq!(slices[1].arch, "arm64");
+        assert!(slices[1].signed);
+        let data = reader.slice_data(&slices[1]);
+        assert_eq!(data, reader.extract("arm64").unwrap());
+
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let slices = reader.slices().unwrap();
+        assert!(slices.iter().all(|s| s.kind == PayloadKind::Archive));
+    }
+
+    #[test]
+    fn test_fat_reader_find_by_uuid() {
+        use crate::write::FatWriter;
+
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let uuids = reader.uuids().unwrap();
+        assert_eq!(uuids.len(), 2);
+        assert_eq!(uuids[1].0, "arm64");
+        let slice = reader.find_by_uuid(uuids[1].1).unwrap().unwrap();
+        assert_eq!(slice.arch, "arm64");
+        assert!(reader.find_by_uuid(uuid::Uuid::nil()).unwrap().is_none());
+
+        // Static library members with UUIDs
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64.a").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/uuids_arm64.a").unwrap())
+            .unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let uuids = reader.uuids().unwrap();
+        assert_eq!(uuids.len(), 2);
+        assert!(uuids.iter().all(|(arch, _)| *arch == "arm64"));
+        assert_ne!(uuids[0].1, uuids[1].1);
+        let slice = reader.find_by_uuid(uuids[1].1).unwrap().unwrap();
+        assert_eq!(slice.arch, "arm64");
+    }
+
+    #[test]
+    fn test_fat_reader_fat64() {
+        use crate::write::FatWriter;
+
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let mut fat = FatWriter::new();
+        fat.add(buf.clone()).unwrap();
+        fat.set_fat64(true);
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.narches, 3);
+        let manifest = reader.manifest().unwrap();
+        assert_eq!(manifest.header.magic, 0xcafebabf);
+        assert!(manifest.header.fat64);
+        let original = FatReader::new(&buf).unwrap();
+        for (entry, arch) in manifest.arches.iter().zip(["x86_64", "x86_64h", "arm64"]) {
+            assert_eq!(entry.arch, arch);
+            assert_eq!(reader.extract(arch), original.extract(arch));
+        }
+
+        // Rebuilt from its manifest
+        let mut rebuilt = Vec::new();
+        manifest
+            .build(&out)
+            .unwrap()
+            .write_to(&mut rebuilt)
+            .unwrap();
+        assert_eq!(rebuilt, out);
+
+        // Truncated fat arch entries
+        assert!(FatReader::new(&out[..40]).is_err());
+    }
+
+    #[test]
+    fn test_fat_reader_manifest() {
+        let buf = fs::read("tests/fixtures/hellofat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let manifest = reader.manifest().unwrap();
+        assert_eq!(manifest.header.magic, 0xcafebabe);
+        assert!(!manifest.header.fat64);
+        assert_eq!(manifest.header.nfat_arch, 3);
+        let arches: Vec<_> = manifest.arches.iter().map(|a| a.arch.as_str()).collect();
+        assert_eq!(arches, vec!["x86_64", "x86_64h", "arm64"]);
+        assert_eq!(manifest.arches[0].offset, 16384);
+        assert_eq!(manifest.arches[0].align, 14);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_fat_reader_to_manifest() {
+        use crate::manifest::FatManifest;
+
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let json = reader.to_manifest().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["header"]["nfat_arch"], 2);
+        assert_eq!(value["arches"][0]["arch"], "x86_64");
+        assert_eq!(value["arches"][0]["kind"], "Archive");
+        let manifest: FatManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(manifest, reader.manifest().unwrap());
+    }
+
+    #[cfg(feature = "codesign")]
+    #[test]
+    fn test_fat_reader_code_signatures() {
+        let buf = fs::read("tests/fixtures/simplefat").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let signatures = reader.code_signatures().unwrap();
+        assert_eq!(signatures.len(), 2);
+        let (arch, signature) = &signatures[1];
+        assert_eq!(*arch, "arm64");
+        assert!(signature.as_ref().unwrap().is_adhoc());
+
+        let buf = fs::read("tests/fixtures/simplefat.a").unwrap();
+        let reader = FatReader::new(&buf).unwrap();
+        let signatures = reader.code_signatures().unwrap();
+        assert!(signatures.iter().all(|(_, signature)| signature.is_none()));
+    }
 }
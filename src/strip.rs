@@ -0,0 +1,309 @@
+//! Removal of data that goes stale when thin Mach-O binaries are modified
+use goblin::mach::{
+    header::MH_OBJECT,
+    load_command::{
+        CommandVariant, LC_CODE_SIGNATURE, LC_DATA_IN_CODE, LC_DYLD_CHAINED_FIXUPS,
+        LC_DYLD_EXPORTS_TRIE, LC_DYLD_INFO, LC_DYLD_INFO_ONLY, LC_DYLIB_CODE_SIGN_DRS, LC_DYSYMTAB,
+        LC_FUNCTION_STARTS, LC_LINKER_OPTIMIZATION_HINT, LC_SEGMENT_SPLIT_INFO, LC_SYMTAB,
+        LC_TWOLEVEL_HINTS,
+    },
+    MachO,
+};
+
+use crate::{
+    cputype::get_align_from_cpu_types,
+    error::Error,
+    macho::{read_u32, segname, write_u32, MachLayout},
+};
+
+const LC_ATOM_INFO: u32 = 0x36;
+
+/// Remove the code signature of a thin Mach-O binary
+///
+/// The `LC_CODE_SIGNATURE` load command is removed and `__LINKEDIT` is truncated
+/// to end before the signature. Unsigned binaries are returned unchanged.
+pub fn remove_code_signature(macho: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut layout = MachLayout::parse(macho)?;
+    let signature = layout
+        .load_commands
+        .iter()
+        .find_map(|lc| match &lc.command {
+            CommandVariant::CodeSignature(cmd) => Some((lc.offset, *cmd)),
+            _ => None,
+        });
+    let (cmd_offset, cmd) = match signature {
+        Some(signature) => signature,
+        None => return Ok(macho.to_vec()),
+    };
+    let linkedit = layout
+        .segment("__LINKEDIT")
+        .ok_or_else(|| Error::InvalidMachO("missing __LINKEDIT segment".to_string()))?;
+    let dataoff = cmd.dataoff as u64;
+    let linkedit_end = linkedit.fileoff + linkedit.filesize;
+    if dataoff < linkedit.fileoff || dataoff + cmd.datasize as u64 > linkedit_end {
+        return Err(Error::InvalidMachO(
+            "code signature is not inside __LINKEDIT".to_string(),
+        ));
+    }
+    if linkedit_end > macho.len() as u64 {
+        return Err(Error::InvalidMachO(
+            "__LINKEDIT extends past the end of the file".to_string(),
+        ));
+    }
+    // The signature must be the last thing in __LINKEDIT for it to be truncated
+    if macho[(dataoff + cmd.datasize as u64) as usize..linkedit_end as usize]
+        .iter()
+        .any(|&b| b != 0)
+    {
+        return Err(Error::InvalidMachO(
+            "code signature is not at the end of __LINKEDIT".to_string(),
+        ));
+    }
+
+    let mut out = macho[..dataoff as usize].to_vec();
+    layout.remove_load_command(&mut out, cmd_offset);
+    let linkedit_size = dataoff - linkedit.fileoff;
+    let page_align = get_align_from_cpu_types(layout.cputype, layout.cpusubtype).max(0x1000);
+    linkedit.set_filesize(&mut out, linkedit_size);
+    linkedit.set_vmsize(
+        &mut out,
+        linkedit
+            .vmsize
+            .min(linkedit_size.div_ceil(page_align as u64) * page_align as u64),
+    );
+    Ok(out)
+}
+
+/// Check whether a thin Mach-O binary contains embedded bitcode
+///
+/// Looks for an `__LLVM` segment as produced by `-fembed-bitcode` when linking,
+/// or `__LLVM` sections such as `__LLVM,__bitcode` in object files.
+pub fn has_embedded_bitcode(macho: &[u8]) -> Result<bool, Error> {
+    let macho = MachO::parse(macho, 0)?;
+    for segment in &macho.segments {
+        if segname(&segment.segname) == "__LLVM" {
+            return Ok(true);
+        }
+        for (section, _) in segment.sections()? {
+            if segname(&section.segname) == "__LLVM" {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Remove the `__LLVM` segment holding the embedded bitcode of a thin Mach-O binary
+///
+/// The segment contents are cut out of the file and the file offsets of the
+/// following `__LINKEDIT` data are moved down, as is the address of `__LINKEDIT`
+/// when it's mapped after `__LLVM`. Symbols defined in `__LLVM` are rejected.
+/// The code signature is removed as well since it no longer matches, binaries
+/// without bitcode are returned unchanged.
+/// Object files keep their bitcode in `__LLVM` sections of their single segment,
+/// removing it isn't supported and fails.
+pub fn remove_embedded_bitcode(macho: &[u8]) -> Result<Vec<u8>, Error> {
+    if !has_embedded_bitcode(macho)? {
+        return Ok(macho.to_vec());
+    }
+    if MachO::parse(macho, 0)?.header.filetype == MH_OBJECT {
+        return Err(Error::InvalidMachO(
+            "removing embedded bitcode from object files is not supported".to_string(),
+        ));
+    }
+    let macho = remove_code_signature(macho)?;
+    let mut layout = MachLayout::parse(&macho)?;
+    let llvm = layout.segment("__LLVM").ok_or_else(|| {
+        Error::InvalidMachO("embedded bitcode sections outside of an __LLVM segment".to_string())
+    })?;
+    let start = llvm.fileoff;
+    let end = start + llvm.filesize;
+    if end > macho.len() as u64 {
+        return Err(Error::InvalidMachO(
+            "__LLVM extends past the end of the file".to_string(),
+        ));
+    }
+    // Section numbers of the remaining segments must not change,
+    // and only __LINKEDIT may follow the bitcode in the file or in memory
+    let llvm_vmend = llvm.vmaddr + llvm.vmsize;
+    let mut after_llvm = false;
+    for (name, segment) in layout.segments() {
+        if segment.cmd_offset == llvm.cmd_offset {
+            after_llvm = true;
+            continue;
+        }
+        if after_llvm && segment.nsects != 0 {
+            return Err(Error::InvalidMachO(format!(
+                "unsupported segment {} with sections after __LLVM",
+                name
+            )));
+        }
+        if segment.filesize != 0
+            && segment.fileoff + segment.filesize > start
+            && (segment.fileoff < end || name != "__LINKEDIT")
+        {
+            return Err(Error::InvalidMachO(format!(
+                "unsupported segment {} after __LLVM in the file",
+                name
+            )));
+        }
+        if segment.vmsize != 0 && segment.vmaddr >= llvm_vmend && name != "__LINKEDIT" {
+            return Err(Error::InvalidMachO(format!(
+                "unsupported segment {} after __LLVM in memory",
+                name
+            )));
+        }
+    }
+    // The __LLVM sections are numbered last, so only symbols pointing into them break
+    let first_sect = layout
+        .segments()
+        .take_while(|(_, segment)| segment.cmd_offset != llvm.cmd_offset)
+        .map(|(_, segment)| segment.nsects as usize)
+        .sum::<usize>()
+        + 1;
+    let llvm_sects = first_sect..first_sect + llvm.nsects as usize;
+    for symbol in MachO::parse(&macho, 0)?.symbols() {
+        let (name, nlist) = symbol?;
+        if llvm_sects.contains(&nlist.n_sect) {
+            return Err(Error::InvalidMachO(format!(
+                "symbol {} is defined in __LLVM",
+                name
+            )));
+        }
+    }
+
+    let mut out = Vec::with_capacity(macho.len() - llvm.filesize as usize);
+    out.extend_from_slice(&macho[..start as usize]);
+    out.extend_from_slice(&macho[end as usize..]);
+    for (_, segment) in layout.segments() {
+        if segment.filesize != 0 && segment.fileoff >= end {
+            segment.set_fileoff(&mut out, segment.fileoff - llvm.filesize);
+        }
+        if segment.vmsize != 0 && segment.vmaddr >= llvm_vmend {
+            segment.set_vmaddr(&mut out, segment.vmaddr - llvm.vmsize);
+        }
+    }
+    for lc in &layout.load_commands {
+        let fields: &[usize] = match read_u32(&out, lc.offset) {
+            LC_SYMTAB => &[8, 16],
+            LC_DYSYMTAB => &[32, 40, 48, 56, 64, 72],
+            LC_DYLD_INFO | LC_DYLD_INFO_ONLY => &[8, 16, 24, 32, 40],
+            LC_CODE_SIGNATURE
+            | LC_SEGMENT_SPLIT_INFO
+            | LC_FUNCTION_STARTS
+            | LC_DATA_IN_CODE
+            | LC_DYLIB_CODE_SIGN_DRS
+            | LC_LINKER_OPTIMIZATION_HINT
+            | LC_DYLD_EXPORTS_TRIE
+            | LC_DYLD_CHAINED_FIXUPS
+            | LC_TWOLEVEL_HINTS
+            | LC_ATOM_INFO => &[8],
+            _ => &[],
+        };
+        for field in fields {
+            let offset = read_u32(&out, lc.offset + field) as u64;
+            if offset >= end {
+                write_u32(&mut out, lc.offset + field, (offset - llvm.filesize) as u32);
+            } else if offset > start {
+                return Err(Error::InvalidMachO(
+                    "load command refers to data inside __LLVM".to_string(),
+                ));
+            }
+        }
+    }
+    layout.remove_load_command(&mut out, llvm.cmd_offset);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use goblin::mach::{load_command::CommandVariant, MachO};
+
+    use super::{has_embedded_bitcode, remove_code_signature, remove_embedded_bitcode};
+
+    #[test]
+    fn test_remove_code_signature() {
+        let buf = fs::read("tests/fixtures/thin_arm64").unwrap();
+        let stripped = remove_code_signature(&buf).unwrap();
+        assert_eq!(stripped.len(), 16544);
+        let macho = MachO::parse(&stripped, 0).unwrap();
+        assert!(!macho
+            .load_commands
+            .iter()
+            .any(|lc| matches!(lc.command, CommandVariant::CodeSignature(_))));
+        let original = MachO::parse(&buf, 0).unwrap();
+        assert_eq!(macho.header.ncmds + 1, original.header.ncmds);
+        assert_eq!(macho.header.sizeofcmds + 16, original.header.sizeofcmds);
+        let linkedit = macho
+            .segments
+            .iter()
+            .find(|seg| seg.name().unwrap() == "__LINKEDIT")
+            .unwrap();
+        assert_eq!(linkedit.fileoff + linkedit.filesize, stripped.len() as u64);
+
+        // Unsigned binaries are left alone
+        let buf = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert_eq!(remove_code_signature(&buf).unwrap(), buf);
+    }
+
+    #[test]
+    fn test_remove_embedded_bitcode() {
+        let buf = fs::read("tests/fixtures/thin_x86_64_bitcode").unwrap();
+        assert!(has_embedded_bitcode(&buf).unwrap());
+        let stripped = remove_embedded_bitcode(&buf).unwrap();
+        assert!(!has_embedded_bitcode(&stripped).unwrap());
+        assert_eq!(stripped.len(), buf.len() - 4096);
+
+        let macho = MachO::parse(&stripped, 0).unwrap();
+        let original = MachO::parse(&buf, 0).unwrap();
+        assert_eq!(macho.header.ncmds + 1, original.header.ncmds);
+        let linkedit = macho
+            .segments
+            .iter()
+            .find(|seg| seg.name().unwrap() == "__LINKEDIT")
+            .unwrap();
+        assert_eq!(linkedit.fileoff, 16384);
+        assert_eq!(linkedit.fileoff + linkedit.filesize, stripped.len() as u64);
+        // Symbols are still readable from the moved __LINKEDIT
+        let symbols: Vec<_> = macho.symbols().map(|sym| sym.unwrap().0).collect();
+        let original_symbols: Vec<_> = original.symbols().map(|sym| sym.unwrap().0).collect();
+        assert_eq!(symbols, original_symbols);
+
+        let buf = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        assert!(!has_embedded_bitcode(&buf).unwrap());
+        assert_eq!(remove_embedded_bitcode(&buf).unwrap(), buf);
+    }
+
+    #[test]
+    fn test_remove_embedded_bitcode_linkedit_address() {
+        let mut buf = fs::read("tests/fixtures/thin_arm64_ios_bitcode").unwrap();
+        let err = remove_embedded_bitcode(&buf).unwrap_err();
+        assert_eq!(err.to_string(), "symbol _bundle is defined in __LLVM");
+
+        // Move the _bundle symbol to __TEXT,__text, the first section
+        let symoff = 0x8000;
+        assert_eq!(buf[symoff + 5], 2);
+        buf[symoff + 5] = 1;
+        let stripped = remove_embedded_bitcode(&buf).unwrap();
+        assert_eq!(stripped.len(), buf.len() - 0x4000);
+        let macho = MachO::parse(&stripped, 0).unwrap();
+        assert_eq!(macho.segments.len(), 2);
+        let linkedit = macho
+            .segments
+            .iter()
+            .find(|seg| seg.name().unwrap() == "__LINKEDIT")
+            .unwrap();
+        assert_eq!(linkedit.vmaddr, 0x4000);
+        assert_eq!(linkedit.fileoff, 0x4000);
+        let symbols: Vec<_> = macho
+            .symbols()
+            .map(|sym| {
+                let (name, nlist) = sym.unwrap();
+                (name, nlist.n_sect)
+            })
+            .collect();
+        assert_eq!(symbols, vec![("_bundle", 1), ("_hello", 1)]);
+    }
+}
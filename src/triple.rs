@@ -0,0 +1,318 @@
+//! Target triple parsing and the Mach-O architectures of triples
+use std::{fmt, str::FromStr};
+
+use goblin::mach::cputype::{
+    CpuSubType, CpuType, CPU_SUBTYPE_ARM64_32_ALL, CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_ARM64_E,
+    CPU_SUBTYPE_ARM_V4T, CPU_SUBTYPE_ARM_V5TEJ, CPU_SUBTYPE_ARM_V6, CPU_SUBTYPE_ARM_V6M,
+    CPU_SUBTYPE_ARM_V7, CPU_SUBTYPE_ARM_V7EM, CPU_SUBTYPE_ARM_V7F, CPU_SUBTYPE_ARM_V7K,
+    CPU_SUBTYPE_ARM_V7M, CPU_SUBTYPE_ARM_V7S, CPU_SUBTYPE_ARM_V8, CPU_SUBTYPE_I386_ALL,
+    CPU_SUBTYPE_POWERPC_ALL, CPU_SUBTYPE_X86_64_ALL, CPU_SUBTYPE_X86_64_H, CPU_TYPE_ARM,
+    CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_I386, CPU_TYPE_POWERPC, CPU_TYPE_POWERPC64,
+    CPU_TYPE_X86_64,
+};
+
+use crate::{
+    cputype::PtrAuthAbi,
+    error::Error,
+    info::{Platform, Version},
+};
+
+/// Architecture component of a triple to Mach-O cputype and cpusubtype
+const ARCHES: &[(&str, CpuType, CpuSubType)] = &[
+    ("i386", CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL),
+    ("i486", CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL),
+    ("i586", CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL),
+    ("i686", CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL),
+    ("x86_64", CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
+    ("amd64", CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
+    ("x86_64h", CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_H),
+    ("powerpc", CPU_TYPE_POWERPC, CPU_SUBTYPE_POWERPC_ALL),
+    ("ppc", CPU_TYPE_POWERPC, CPU_SUBTYPE_POWERPC_ALL),
+    ("powerpc64", CPU_TYPE_POWERPC64, CPU_SUBTYPE_POWERPC_ALL),
+    ("ppc64", CPU_TYPE_POWERPC64, CPU_SUBTYPE_POWERPC_ALL),
+    ("arm", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V4T),
+    ("armv4t", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V4T),
+    ("thumb", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V4T),
+    ("armv5", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V5TEJ),
+    ("armv5e", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V5TEJ),
+    ("armv5te", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V5TEJ),
+    ("thumbv5", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V5TEJ),
+    ("thumbv5e", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V5TEJ),
+    ("armv6", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6),
+    ("armv6k", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6),
+    ("thumbv6", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6),
+    ("armv6m", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6M),
+    ("thumbv6m", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6M),
+    ("armv7", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7),
+    ("armv7a", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7),
+    ("thumbv7", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7),
+    ("armv7f", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7F),
+    ("thumbv7f", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7F),
+    ("armv7s", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S),
+    ("thumbv7s", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S),
+    ("armv7k", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7K),
+    ("thumbv7k", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7K),
+    ("armv7m", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7M),
+    ("thumbv7m", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7M),
+    ("armv7em", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7EM),
+    ("thumbv7em", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7EM),
+    ("armv8", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V8),
+    ("armv8a", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V8),
+    ("thumbv8", CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V8),
+    ("arm64", CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL),
+    ("aarch64", CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL),
+    ("arm64e", CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E),
+    ("aarch64e", CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E),
+    ("arm64_32", CPU_TYPE_ARM64_32, CPU_SUBTYPE_ARM64_32_ALL),
+    ("aarch64_32", CPU_TYPE_ARM64_32, CPU_SUBTYPE_ARM64_32_ALL),
+];
+
+/// Bits of arm64e cpusubtypes encoding the pointer authentication ABI
+const PTRAUTH_FLAGS: CpuSubType = 0xff00_0000;
+
+/// OS component of a triple, without version, to the platform it targets
+const OSES: &[(&str, Platform)] = &[
+    ("darwin", Platform::MacOS),
+    ("macos", Platform::MacOS),
+    ("macosx", Platform::MacOS),
+    ("ios", Platform::IOS),
+    ("tvos", Platform::TvOS),
+    ("watchos", Platform::WatchOS),
+    ("bridgeos", Platform::BridgeOS),
+    ("driverkit", Platform::DriverKit),
+    ("xros", Platform::VisionOS),
+    ("visionos", Platform::VisionOS),
+];
+
+/// A parsed `arch-vendor-os[-environment]` target triple
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Triple {
+    pub arch: String,
+    pub vendor: String,
+    /// OS name without the version
+    pub os: String,
+    /// Deployment target encoded in the OS component, e.g. `14.0` in `ios14.0`
+    pub os_version: Option<Version>,
+    /// Environment such as `simulator` or `macabi`
+    pub environment: Option<String>,
+    pub cputype: CpuType,
+    pub cpusubtype: CpuSubType,
+}
+
+impl Triple {
+    /// Parse a target triple, the vendor and OS components are optional
+    pub fn parse(triple: &str) -> Result<Self, Error> {
+        let unsupported = || Error::UnsupportedTriple(triple.to_string());
+        let mut components = triple.split('-');
+        let arch = components.next().unwrap_or_default();
+        let (cputype, cpusubtype) = ARCHES
+            .iter()
+            .find(|(name, _, _)| *name == arch)
+            .map(|(_, cputype, cpusubtype)| (*cputype, *cpusubtype))
+            .ok_or_else(unsupported)?;
+        let vendor = components.next().unwrap_or("unknown");
+        let os = components.next().unwrap_or("unknown");
+        let environment = components.next();
+        if components.next().is_some()
+            || [vendor, os].contains(&"")
+            || environment.is_some_and(str::is_empty)
+        {
+            return Err(unsupported());
+        }
+        let version_start = os.find(|c: char| c.is_ascii_digit()).unwrap_or(os.len());
+        let (os_name, version) = os.split_at(version_start);
+        let os_version = if version.is_empty() {
+            None
+        } else {
+            Some(parse_version(version).ok_or_else(unsupported)?)
+        };
+        Ok(Self {
+            arch: arch.to_string(),
+            vendor: vendor.to_string(),
+            os: os_name.to_string(),
+            os_version,
+            environment: environment.map(|env| env.to_string()),
+            cputype,
+            cpusubtype,
+        })
+    }
+
+    /// Pointer authentication ABI of an `arm64e` triple, `None` if it isn't versioned
+    pub fn ptrauth_abi(&self) -> Option<PtrAuthAbi> {
+        if self.cputype != CPU_TYPE_ARM64 {
+            return None;
+        }
+        PtrAuthAbi::from_cpusubtype(self.cpusubtype)
+    }
+
+    /// Set the pointer authentication ABI of an `arm64e` triple, updating its cpusubtype
+    ///
+    /// Triples don't spell out the ABI version, clang takes it from `-fptrauth-abi-version`.
+    pub fn set_ptrauth_abi(&mut self, abi: Option<PtrAuthAbi>) -> Result<(), Error> {
+        if (self.cputype, self.cpusubtype & !PTRAUTH_FLAGS) != (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E)
+        {
+            return Err(Error::UnsupportedTriple(format!(
+                "{} (pointer authentication requires arm64e)",
+                self
+            )));
+        }
+        self.cpusubtype = match abi {
+            Some(abi) => abi.cpusubtype()?,
+            None => CPU_SUBTYPE_ARM64_E,
+        };
+        Ok(())
+    }
+
+    /// Apple platform targeted by the triple, taking simulator and Mac Catalyst
+    /// environments into account
+    pub fn platform(&self) -> Option<Platform> {
+        let platform = OSES
+            .iter()
+            .find(|(name, _)| *name == self.os)
+            .map(|(_, platform)| *platform)?;
+        Some(match (platform, self.environment.as_deref()) {
+            (Platform::IOS, Some("simulator")) => Platform::IOSSimulator,
+            (Platform::IOS, Some("macabi")) => Platform::MacCatalyst,
+            (Platform::TvOS, Some("simulator")) => Platform::TvOSSimulator,
+            (Platform::WatchOS, Some("simulator")) => Platform::WatchOSSimulator,
+            (Platform::VisionOS, Some("simulator")) => Platform::VisionOSSimulator,
+            (platform, _) => platform,
+        })
+    }
+}
+
+impl FromStr for Triple {
+    type Err = Error;
+
+    fn from_str(triple: &str) -> Result<Self, Self::Err> {
+        Self::parse(triple)
+    }
+}
+
+impl fmt::Display for Triple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.arch, self.vendor, self.os)?;
+        if let Some(version) = self.os_version {
+            write!(f, "{}", version)?;
+        }
+        if let Some(environment) = &self.environment {
+            write!(f, "-{}", environment)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_version(version: &str) -> Option<Version> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+    let patch = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Version {
+        major,
+        minor,
+        patch,
+    })
+}
+
+/// Mach-O cputype and cpusubtype of a target triple
+pub fn get_arch_from_triple(triple: &str) -> Result<(CpuType, CpuSubType), Error> {
+    let triple = Triple::parse(triple)?;
+    Ok((triple.cputype, triple.cpusubtype))
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::mach::cputype::{
+        CPU_SUBTYPE_ARM64_E, CPU_SUBTYPE_ARM_V8, CPU_SUBTYPE_X86_64_H, CPU_TYPE_ARM,
+        CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_X86_64,
+    };
+
+    use super::{get_arch_from_triple, Triple};
+    use crate::{
+        cputype::PtrAuthAbi,
+        error::Error,
+        info::{Platform, Version},
+    };
+
+    #[test]
+    fn test_parse_triple() {
+        let triple = Triple::parse("arm64-apple-ios14.0-simulator").unwrap();
+        assert_eq!(triple.arch, "arm64");
+        assert_eq!(triple.vendor, "apple");
+        assert_eq!(triple.os, "ios");
+        assert_eq!(triple.os_version, Some(Version::from_raw(0x000e_0000)));
+        assert_eq!(triple.environment.as_deref(), Some("simulator"));
+        assert_eq!(triple.cputype, CPU_TYPE_ARM64);
+        assert_eq!(triple.platform(), Some(Platform::IOSSimulator));
+        assert_eq!(triple.to_string(), "arm64-apple-ios14.0-simulator");
+
+        let triple = Triple::parse("x86_64h-apple-ios13.1-macabi").unwrap();
+        assert_eq!(
+            (triple.cputype, triple.cpusubtype),
+            (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_H)
+        );
+        assert_eq!(triple.platform(), Some(Platform::MacCatalyst));
+
+        let triple = Triple::parse("aarch64-apple-darwin").unwrap();
+        assert_eq!(triple.platform(), Some(Platform::MacOS));
+        assert_eq!(triple.os_version, None);
+
+        for triple in ["arm64-apple-ios-", "arm64--ios", "arm64-apple-", "arm64-"] {
+            assert!(Triple::parse(triple).is_err(), "{}", triple);
+        }
+    }
+
+    #[test]
+    fn test_get_arch_from_triple() {
+        assert_eq!(
+            get_arch_from_triple("arm64e-apple-macosx11.0.0").unwrap(),
+            (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E)
+        );
+        assert_eq!(
+            get_arch_from_triple("arm64_32-apple-watchos8.0").unwrap().0,
+            CPU_TYPE_ARM64_32
+        );
+        assert_eq!(
+            get_arch_from_triple("armv8").unwrap(),
+            (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V8)
+        );
+        let err = get_arch_from_triple("riscv64-unknown-linux-gnu").unwrap_err();
+        assert!(
+            matches!(&err, Error::UnsupportedTriple(triple) if triple == "riscv64-unknown-linux-gnu")
+        );
+        assert_eq!(
+            err.to_string(),
+            "unsupported target triple riscv64-unknown-linux-gnu"
+        );
+        assert!(get_arch_from_triple("arm64-apple-ios1x").is_err());
+    }
+
+    #[test]
+    fn test_triple_ptrauth_abi() {
+        let mut triple = Triple::parse("arm64e-apple-ios14.0").unwrap();
+        assert_eq!(triple.ptrauth_abi(), None);
+        let abi = PtrAuthAbi {
+            version: 0,
+            kernel: false,
+        };
+        triple.set_ptrauth_abi(Some(abi)).unwrap();
+        assert_eq!(triple.cpusubtype, 0x8000_0002);
+        assert_eq!(triple.ptrauth_abi(), Some(abi));
+        let abi = PtrAuthAbi {
+            version: 5,
+            kernel: true,
+        };
+        triple.set_ptrauth_abi(Some(abi)).unwrap();
+        assert_eq!(triple.cpusubtype, 0xc500_0002);
+        assert_eq!(triple.ptrauth_abi(), Some(abi));
+        triple.set_ptrauth_abi(None).unwrap();
+        assert_eq!(triple.cpusubtype, CPU_SUBTYPE_ARM64_E);
+
+        let mut triple = Triple::parse("arm64-apple-ios14.0").unwrap();
+        assert!(triple.set_ptrauth_abi(Some(abi)).is_err());
+        assert_eq!(triple.ptrauth_abi(), None);
+    }
+}
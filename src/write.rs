@@ -3,42 +3,37 @@
 use std::os::unix::fs::PermissionsExt;
 use std::{
     cmp::Ordering,
-    fs::File,
-    io::{self, BufWriter, Write},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
 #[cfg(feature = "bitcode")]
-use goblin::mach::cputype::{
-    CPU_SUBTYPE_ARM64_32_ALL, CPU_SUBTYPE_ARM64_ALL, CPU_SUBTYPE_ARM64_E, CPU_SUBTYPE_ARM_V4T,
-    CPU_SUBTYPE_ARM_V5TEJ, CPU_SUBTYPE_ARM_V6, CPU_SUBTYPE_ARM_V6M, CPU_SUBTYPE_ARM_V7,
-    CPU_SUBTYPE_ARM_V7EM, CPU_SUBTYPE_ARM_V7F, CPU_SUBTYPE_ARM_V7K, CPU_SUBTYPE_ARM_V7M,
-    CPU_SUBTYPE_ARM_V7S, CPU_SUBTYPE_I386_ALL, CPU_SUBTYPE_POWERPC_ALL, CPU_SUBTYPE_X86_64_ALL,
-    CPU_SUBTYPE_X86_64_H,
-};
+use crate::bitcode::{get_arch_from_bitcode, is_raw_bitcode, wrap_bitcode};
+#[cfg(feature = "codesign")]
+use crate::codesign::{adhoc_sign, is_signable, SigningOptions};
+use crate::cputype::{arch_name, get_align_from_cpu_types, is_arch, Arch};
+use crate::error::Error;
+use crate::info::{Platform, SliceInfo};
+use crate::layout::{Layout, SliceLayout};
+use crate::manifest::FAT_MAGIC_64;
+use crate::read::FatReader;
+use crate::strip::{has_embedded_bitcode, remove_code_signature, remove_embedded_bitcode};
 use goblin::{
     archive::Archive,
     mach::{
-        cputype::{
-            get_arch_from_flag, get_arch_name_from_types, CpuSubType, CpuType, CPU_ARCH_ABI64,
-            CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_HPPA, CPU_TYPE_I386,
-            CPU_TYPE_I860, CPU_TYPE_MC680X0, CPU_TYPE_MC88000, CPU_TYPE_POWERPC,
-            CPU_TYPE_POWERPC64, CPU_TYPE_SPARC, CPU_TYPE_X86_64,
-        },
-        fat::{FAT_MAGIC, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
+        cputype::{CpuSubType, CpuType, CPU_ARCH_ABI64, CPU_TYPE_ARM64},
+        fat::{SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
         Mach,
     },
     Object,
 };
-#[cfg(feature = "bitcode")]
-use llvm_bitcode::{bitcode::BitcodeElement, Bitcode};
-
-use crate::error::Error;
 
-const FAT_MAGIC_64: u32 = FAT_MAGIC + 1;
 const SIZEOF_FAT_ARCH_64: usize = 32;
 
-const LLVM_BITCODE_WRAPPER_MAGIC: u32 = 0x0B17C0DE;
+pub(crate) const LLVM_BITCODE_WRAPPER_MAGIC: u32 = 0x0B17C0DE;
+/// `BC\xC0\xDE` read as a little-endian u32
+pub(crate) const LLVM_BITCODE_MAGIC: u32 = 0xDEC04342;
 
 #[derive(Debug)]
 struct ThinArch {
@@ -46,6 +41,15 @@ struct ThinArch {
     cpu_type: u32,
     cpu_subtype: u32,
     align: i64,
+    /// Offset in the explicit layout, computed from the alignment when unset
+    offset: Option<u64>,
+    platform: Option<Platform>,
+}
+
+impl ThinArch {
+    fn name(&self) -> &'static str {
+        arch_name(self.cpu_type, self.cpu_subtype).unwrap_or("unknown")
+    }
 }
 
 /// Mach-O fat binary writer
@@ -54,6 +58,10 @@ pub struct FatWriter {
     arches: Vec<ThinArch>,
     max_align: i64,
     is_fat64: bool,
+    explicit_layout: bool,
+    mixed_platforms: bool,
+    #[cfg(feature = "bitcode")]
+    wrap_bitcode: bool,
 }
 
 #[inline]
@@ -67,6 +75,12 @@ fn unpack_u32(buf: &[u8]) -> io::Result<u32> {
     Ok(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]))
 }
 
+impl Default for FatWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FatWriter {
     /// Create a new Mach-O fat binary writer
     pub fn new() -> Self {
@@ -74,12 +88,27 @@ impl FatWriter {
             arches: Vec::new(),
             max_align: 0,
             is_fat64: false,
+            explicit_layout: false,
+            mixed_platforms: false,
+            #[cfg(feature = "bitcode")]
+            wrap_bitcode: false,
         }
     }
 
     /// Add a new thin Mach-O binary
+    ///
+    /// Slices built for platforms that can't share a fat binary, such as macOS
+    /// and iOS, are rejected unless enabled with [`FatWriter::set_mixed_platforms`].
     pub fn add<T: Into<Vec<u8>>>(&mut self, bytes: T) -> Result<(), Error> {
         let bytes = bytes.into();
+        // goblin only parses the fat32 format
+        if bytes.get(..4) == Some(&FAT_MAGIC_64.to_be_bytes()[..]) {
+            let reader = FatReader::new(&bytes)?;
+            for arch in reader.iter_arches() {
+                self.add(arch.slice(&bytes).to_vec())?;
+            }
+            return Ok(());
+        }
         match Object::parse(&bytes)? {
             Object::Mach(mach) => match mach {
                 Mach::Fat(fat) => {
@@ -92,31 +121,19 @@ impl FatWriter {
                     let header = obj.header;
                     let cpu_type = header.cputype;
                     let cpu_subtype = header.cpusubtype;
-                    // Check if this architecture already exists
-                    if self
-                        .arches
-                        .iter()
-                        .find(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
-                        .is_some()
-                    {
-                        let arch =
-                            get_arch_name_from_types(cpu_type, cpu_subtype).unwrap_or("unknown");
-                        return Err(Error::DuplicatedArch(arch.to_string()));
-                    }
-                    if header.magic == FAT_MAGIC_64 {
-                        self.is_fat64 = true;
-                    }
-                    let align = get_align_from_cpu_types(cpu_type, cpu_subtype);
-                    if align > self.max_align {
-                        self.max_align = align;
-                    }
+                    let platform = SliceInfo::parse(cpu_type, cpu_subtype, 0, 0, &bytes)?.platform;
                     let thin = ThinArch {
                         data: bytes,
                         cpu_type,
                         cpu_subtype,
-                        align,
+                        align: get_align_from_cpu_types(cpu_type, cpu_subtype),
+                        platform,
+                        offset: None,
                     };
-                    self.arches.push(thin);
+                    self.push(thin)?;
+                    if header.magic == FAT_MAGIC_64 {
+                        self.is_fat64 = true;
+                    }
                 }
             },
             Object::Archive(ar) => {
@@ -126,35 +143,22 @@ impl FatWriter {
                 } else {
                     4 /* alignof(u32) */
                 };
-                if align > self.max_align {
-                    self.max_align = align;
-                }
+                let platform = SliceInfo::parse(cpu_type, cpu_subtype, 0, 0, &bytes)?.platform;
                 let thin = ThinArch {
                     data: bytes,
                     cpu_type,
                     cpu_subtype,
                     align,
+                    platform,
+                    offset: None,
                 };
-                self.arches.push(thin);
+                self.push(thin)?;
             }
             Object::Unknown(_) => {
                 let magic = unpack_u32(&bytes)?;
-                if magic == LLVM_BITCODE_WRAPPER_MAGIC {
+                if magic == LLVM_BITCODE_WRAPPER_MAGIC || magic == LLVM_BITCODE_MAGIC {
                     #[cfg(feature = "bitcode")]
-                    {
-                        let (cpu_type, cpu_subtype) = self.get_arch_from_bitcode(&bytes)?;
-                        let align = 1;
-                        if align > self.max_align {
-                            self.max_align = align;
-                        }
-                        let thin = ThinArch {
-                            data: bytes,
-                            cpu_type,
-                            cpu_subtype,
-                            align,
-                        };
-                        self.arches.push(thin);
-                    }
+                    self.add_bitcode(bytes)?;
 
                     #[cfg(not(feature = "bitcode"))]
                     return Err(Error::InvalidMachO(
@@ -166,6 +170,130 @@ impl FatWriter {
             }
             _ => return Err(Error::InvalidMachO("input is not a macho file".to_string())),
         }
+        self.sort_arches();
+        Ok(())
+    }
+
+    #[cfg(feature = "bitcode")]
+    fn add_bitcode(&mut self, bytes: Vec<u8>) -> Result<(), Error> {
+        let (cpu_type, cpu_subtype) = get_arch_from_bitcode(&bytes)?;
+        let data = if self.wrap_bitcode && is_raw_bitcode(&bytes) {
+            wrap_bitcode(&bytes, cpu_type)
+        } else {
+            bytes
+        };
+        let thin = ThinArch {
+            data,
+            cpu_type,
+            cpu_subtype,
+            align: 1,
+            platform: None,
+            offset: None,
+        };
+        self.push(thin)
+    }
+
+    /// Add a slice of any format without looking at its contents
+    ///
+    /// Useful for payloads goblin can't parse such as firmware images, `align` is
+    /// in bytes and must be a power of two.
+    pub fn add_raw<T: Into<Vec<u8>>>(
+        &mut self,
+        arch: Arch,
+        align: u32,
+        bytes: T,
+    ) -> Result<(), Error> {
+        let thin = ThinArch {
+            data: bytes.into(),
+            cpu_type: arch.cpu_type,
+            cpu_subtype: arch.cpu_subtype,
+            align: align as i64,
+            platform: None,
+            offset: None,
+        };
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment {
+                arch: thin.name().to_string(),
+                align: align as u64,
+            });
+        }
+        self.push(thin)?;
+        self.sort_arches();
+        Ok(())
+    }
+
+    fn push(&mut self, thin: ThinArch) -> Result<(), Error> {
+        self.check_platform(&thin, false)?;
+        // Check if this architecture already exists
+        if self.position(thin.cpu_type, thin.cpu_subtype).is_some() {
+            return Err(Error::DuplicatedArch(thin.name().to_string()));
+        }
+        if thin.align > self.max_align {
+            self.max_align = thin.align;
+        }
+        self.arches.push(thin);
+        Ok(())
+    }
+
+    /// Check the platform of a new slice against the existing slices
+    ///
+    /// Slices of the same architecture are skipped when they are about to be replaced.
+    fn check_platform(&self, thin: &ThinArch, replace: bool) -> Result<(), Error> {
+        let platform = match thin.platform {
+            Some(platform) if !self.mixed_platforms => platform,
+            _ => return Ok(()),
+        };
+        let conflict = self.arches.iter().find(|arch| {
+            let same_arch = arch.cpu_type == thin.cpu_type && arch.cpu_subtype == thin.cpu_subtype;
+            match arch.platform {
+                // Same architecture for a compatible platform is a plain duplicate
+                Some(existing) if same_arch => !replace && existing != platform,
+                Some(existing) => !is_compatible_platform(existing, platform),
+                None => false,
+            }
+        });
+        match conflict {
+            Some(existing) => Err(Error::IncompatiblePlatform {
+                arch: thin.name().to_string(),
+                platform,
+                existing_arch: existing.name().to_string(),
+                existing_platform: existing.platform.unwrap(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a thin or fat binary from a file
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.add(fs::read(path)?)
+    }
+
+    /// Add a thin Mach-O binary, replacing the existing slice(s) of the same architecture
+    ///
+    /// Returns the replaced slices.
+    pub fn replace<T: Into<Vec<u8>>>(&mut self, bytes: T) -> Result<Vec<Vec<u8>>, Error> {
+        let mut other = FatWriter::new();
+        other.mixed_platforms = self.mixed_platforms;
+        other.add(bytes)?;
+        for thin in &other.arches {
+            self.check_platform(thin, true)?;
+        }
+        let mut replaced = Vec::new();
+        for thin in other.arches {
+            if let Some(index) = self.position(thin.cpu_type, thin.cpu_subtype) {
+                replaced.push(self.arches.remove(index).data);
+            }
+            self.arches.push(thin);
+        }
+        self.update_max_align();
+        self.sort_arches();
+        Ok(replaced)
+    }
+
+    fn sort_arches(&mut self) {
+        if self.explicit_layout {
+            return;
+        }
         // Sort the files by alignment to save space in ouput
         self.arches.sort_by(|a, b| {
             if a.cpu_type == b.cpu_type {
@@ -181,76 +309,13 @@ impl FatWriter {
             }
             a.align.cmp(&b.align)
         });
-        Ok(())
-    }
-
-    #[cfg(feature = "bitcode")]
-    fn get_arch_from_bitcode(&self, buffer: &[u8]) -> Result<(CpuType, CpuSubType), Error> {
-        let bitcode = Bitcode::new(buffer)?;
-        let target_triple = bitcode
-            .elements
-            .iter()
-            .find(|ele| match ele {
-                BitcodeElement::Record(_) => false,
-                BitcodeElement::Block(block) => block.id == 8,
-            })
-            .and_then(|module_block| {
-                module_block
-                    .as_block()
-                    .unwrap()
-                    .elements
-                    .iter()
-                    .find(|ele| match ele {
-                        BitcodeElement::Record(record) => record.id == 2,
-                        BitcodeElement::Block(_) => false,
-                    })
-            })
-            .and_then(|target_triple_record| {
-                let record = target_triple_record.as_record().unwrap();
-                let fields: Vec<u8> = record.fields.iter().map(|x| *x as u8).collect();
-                String::from_utf8(fields).ok()
-            });
-        if let Some(triple) = target_triple {
-            if let Some(triple) = triple.splitn(2, "-").next() {
-                return Ok(match triple {
-                    "i686" | "i386" => (CPU_TYPE_I386, CPU_SUBTYPE_I386_ALL),
-                    "x86_64" => (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
-                    "x86_64h" => (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_H),
-                    "powerpc" => (CPU_TYPE_POWERPC, CPU_SUBTYPE_POWERPC_ALL),
-                    "powerpc64" => (CPU_TYPE_POWERPC64, CPU_SUBTYPE_POWERPC_ALL),
-                    "arm" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V4T),
-                    "armv5" | "armv5e" | "thumbv5" | "thumbv5e" => {
-                        (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V5TEJ)
-                    }
-                    "armv6" | "thumbv6" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6),
-                    "armv6m" | "thumbv6m" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V6M),
-                    "armv7" | "thumbv7" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7),
-                    "armv7f" | "thumbv7f" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7F),
-                    "armv7s" | "thumbv7s" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S),
-                    "armv7k" | "thumbv7k" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7K),
-                    "armv7m" | "thumbv7m" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7M),
-                    "armv7em" | "thumbv7em" => (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7EM),
-                    "arm64" => (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL),
-                    "arm64e" => (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_E),
-                    "arm64_32" => (CPU_TYPE_ARM64_32, CPU_SUBTYPE_ARM64_32_ALL),
-                    _ => return Err(Error::InvalidMachO("input is not a macho file".to_string())),
-                });
-            }
-        }
-        Err(Error::InvalidMachO("input is not a macho file".to_string()))
     }
 
     fn check_archive(&self, buffer: &[u8], ar: &Archive) -> Result<(u32, u32), Error> {
         for member in ar.members() {
             let bytes = ar.extract(member, buffer)?;
-            match Object::parse(bytes)? {
-                Object::Mach(mach) => match mach {
-                    Mach::Binary(obj) => {
-                        return Ok((obj.header.cputype, obj.header.cpusubtype));
-                    }
-                    Mach::Fat(_) => {}
-                },
-                _ => {}
+            if let Object::Mach(Mach::Binary(obj)) = Object::parse(bytes)? {
+                return Ok((obj.header.cputype, obj.header.cpusubtype));
             }
         }
         Err(Error::InvalidMachO(
@@ -258,139 +323,369 @@ impl FatWriter {
         ))
     }
 
+    fn position(&self, cpu_type: CpuType, cpu_subtype: CpuSubType) -> Option<usize> {
+        self.arches
+            .iter()
+            .position(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
+    }
+
+    fn find(&self, arch: &str) -> Option<usize> {
+        self.arches
+            .iter()
+            .position(|thin| is_arch(arch, thin.cpu_type, thin.cpu_subtype))
+    }
+
+    fn update_max_align(&mut self) {
+        self.max_align = self.arches.iter().map(|arch| arch.align).max().unwrap_or(0);
+    }
+
     /// Remove an architecture
     pub fn remove(&mut self, arch: &str) -> Option<Vec<u8>> {
-        if let Some((cpu_type, cpu_subtype)) = get_arch_from_flag(arch) {
-            if let Some(index) = self
-                .arches
-                .iter()
-                .position(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
-            {
-                return Some(self.arches.remove(index).data);
-            }
-        }
-        None
+        let index = self.find(arch)?;
+        let thin = self.arches.remove(index);
+        self.update_max_align();
+        Some(thin.data)
     }
 
     /// Check whether a certain architecture exists in this fat binary
     pub fn exists(&self, arch: &str) -> bool {
-        if let Some((cpu_type, cpu_subtype)) = get_arch_from_flag(arch) {
-            return self
-                .arches
-                .iter()
-                .find(|arch| arch.cpu_type == cpu_type && arch.cpu_subtype == cpu_subtype)
-                .is_some();
-        }
-        false
+        self.find(arch).is_some()
     }
 
-    /// Write Mach-O fat binary into the writer
-    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
-        if self.arches.is_empty() {
-            return Ok(());
+    /// Get the thin binary of an architecture
+    pub fn get(&self, arch: &str) -> Option<&[u8]> {
+        self.find(arch)
+            .map(|index| self.arches[index].data.as_slice())
+    }
+
+    /// Names of the architectures in this fat binary, in output order
+    pub fn arches(&self) -> Vec<&'static str> {
+        self.arches.iter().map(|arch| arch.name()).collect()
+    }
+
+    /// Iterate over `(arch name, thin binary)` pairs, in output order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &[u8])> {
+        self.arches
+            .iter()
+            .map(|arch| (arch.name(), arch.data.as_slice()))
+    }
+
+    /// Number of architectures in this fat binary
+    pub fn len(&self) -> usize {
+        self.arches.len()
+    }
+
+    /// Check whether this fat binary has no architectures
+    pub fn is_empty(&self) -> bool {
+        self.arches.is_empty()
+    }
+
+    /// Set the alignment of an architecture, must be a power of two
+    ///
+    /// Slices are laid out using the largest alignment of all architectures,
+    /// unless the explicit layout is enabled with [`FatWriter::set_explicit_layout`].
+    /// Returns `false` if the architecture doesn't exist.
+    pub fn set_align(&mut self, arch: &str, align: u32) -> Result<bool, Error> {
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment {
+                arch: arch.to_string(),
+                align: align as u64,
+            });
         }
-        // Check whether we're doing fat32 or fat64
-        let is_fat64 =
-            if self.is_fat64 || self.arches.last().unwrap().data.len() as i64 >= 1i64 << 32 {
-                true
-            } else {
-                false
-            };
-        let align = self.max_align;
-        let mut total_offset = SIZEOF_FAT_HEADER as i64;
-        if is_fat64 {
-            total_offset += self.arches.len() as i64 * SIZEOF_FAT_ARCH_64 as i64;
-        // narches * size of fat_arch_64
-        } else {
-            total_offset += self.arches.len() as i64 * SIZEOF_FAT_ARCH as i64; // narches * size of fat_arch
+        match self.find(arch) {
+            Some(index) => {
+                self.arches[index].align = align as i64;
+                self.update_max_align();
+                Ok(true)
+            }
+            None => Ok(false),
         }
-        let mut arch_offsets = Vec::with_capacity(self.arches.len());
-        for arch in &self.arches {
-            // Round up to multiple of align
-            total_offset = (total_offset + align - 1) / align * align;
-            arch_offsets.push(total_offset);
-            total_offset += arch.data.len() as i64;
+    }
+
+    /// Set the offset of an architecture in the explicit layout
+    ///
+    /// The offset must be a multiple of the slice alignment and leave room for the
+    /// preceding slices, it is ignored unless the explicit layout is enabled.
+    /// Returns `false` if the architecture doesn't exist.
+    pub fn set_offset(&mut self, arch: &str, offset: u64) -> bool {
+        match self.find(arch) {
+            Some(index) => {
+                self.arches[index].offset = Some(offset);
+                true
+            }
+            None => false,
         }
-        let mut hdr = Vec::with_capacity(12);
-        // Build a fat_header
-        if is_fat64 {
-            hdr.push(FAT_MAGIC_64);
-        } else {
-            hdr.push(FAT_MAGIC);
+    }
+
+    /// Lay out slices in insertion order, each aligned to its own alignment
+    ///
+    /// By default slices are sorted to save space and all use the largest alignment.
+    /// Disabling the explicit layout sorts the slices again.
+    pub fn set_explicit_layout(&mut self, explicit: bool) {
+        self.explicit_layout = explicit;
+        self.sort_arches();
+    }
+
+    /// Whether the explicit layout is enabled
+    pub fn is_explicit_layout(&self) -> bool {
+        self.explicit_layout
+    }
+
+    /// Allow slices built for incompatible platforms, such as macOS and iOS, in one fat binary
+    ///
+    /// Slices of the same architecture are still rejected as duplicates.
+    pub fn set_mixed_platforms(&mut self, allow: bool) {
+        self.mixed_platforms = allow;
+    }
+
+    /// Whether slices built for incompatible platforms are allowed
+    pub fn allows_mixed_platforms(&self) -> bool {
+        self.mixed_platforms
+    }
+
+    /// Wrap raw LLVM bitcode slices in the Darwin bitcode wrapper header
+    ///
+    /// Applies to raw bitcode slices already added as well as to later ones.
+    #[cfg(feature = "bitcode")]
+    pub fn set_wrap_bitcode(&mut self, wrap: bool) {
+        self.wrap_bitcode = wrap;
+        if wrap {
+            for arch in &mut self.arches {
+                if is_raw_bitcode(&arch.data) {
+                    arch.data = wrap_bitcode(&arch.data, arch.cpu_type);
+                }
+            }
         }
-        hdr.push(self.arches.len() as u32);
-        // Compute the max alignment bits
-        let align_bits = (align as f32).log2() as u32;
-        // Build a fat_arch for each arch
-        for (arch, arch_offset) in self.arches.iter().zip(arch_offsets.iter()) {
-            hdr.push(arch.cpu_type);
-            hdr.push(arch.cpu_subtype);
-            if is_fat64 {
-                // Big Endian
-                hdr.push((arch_offset >> 32) as u32);
+    }
+
+    /// Force the fat64 format, by default fat64 is only used when a slice is too large for fat32
+    pub fn set_fat64(&mut self, fat64: bool) {
+        self.is_fat64 = fat64;
+    }
+
+    /// Whether the fat64 format is forced
+    pub fn is_fat64(&self) -> bool {
+        self.is_fat64
+    }
+
+    /// Remove the code signature of every Mach-O binary slice
+    ///
+    /// Modifying a signed slice invalidates its signature, which makes macOS refuse
+    /// to run it, while an unsigned slice can be re-signed afterwards.
+    pub fn strip_signatures(&mut self) -> Result<(), Error> {
+        for arch in &mut self.arches {
+            if let Object::Mach(Mach::Binary(_)) = Object::parse(&arch.data)? {
+                arch.data = remove_code_signature(&arch.data)?;
             }
-            hdr.push(*arch_offset as u32);
-            if is_fat64 {
-                hdr.push((arch.data.len() >> 32) as u32);
+        }
+        Ok(())
+    }
+
+    /// Remove the embedded bitcode of every Mach-O binary slice
+    ///
+    /// The App Store rejects binaries with embedded bitcode since Xcode 14.
+    /// Code signatures of the affected slices are removed as well. Removing the
+    /// bitcode of object files isn't supported, so object files and static libraries
+    /// with embedded bitcode fail with an error. No slice is modified on failure.
+    pub fn strip_bitcode(&mut self) -> Result<(), Error> {
+        let mut stripped = Vec::new();
+        for (index, arch) in self.arches.iter().enumerate() {
+            match Object::parse(&arch.data)? {
+                Object::Mach(Mach::Binary(_)) if has_embedded_bitcode(&arch.data)? => {
+                    stripped.push((index, remove_embedded_bitcode(&arch.data)?));
+                }
+                Object::Archive(archive) => {
+                    for member in archive.members() {
+                        let bytes = archive.extract(member, &arch.data)?;
+                        if let Ok(Object::Mach(Mach::Binary(_))) = Object::parse(bytes) {
+                            if has_embedded_bitcode(bytes)? {
+                                return Err(Error::InvalidMachO(format!(
+                                    "removing embedded bitcode from static libraries is not supported, member {} of the {} slice has embedded bitcode",
+                                    member,
+                                    arch.name()
+                                )));
+                            }
+                        }
+                    }
+                }
+                _ => {}
             }
-            hdr.push(arch.data.len() as u32);
-            hdr.push(align_bits);
-            if is_fat64 {
-                // Reserved
-                hdr.push(0);
+        }
+        for (index, data) in stripped {
+            self.arches[index].data = data;
+        }
+        Ok(())
+    }
+
+    /// Ad-hoc sign every Mach-O binary slice, replacing existing code signatures
+    ///
+    /// Static libraries, object files and bitcode slices are left untouched.
+    #[cfg(feature = "codesign")]
+    pub fn sign_adhoc(&mut self, options: &SigningOptions) -> Result<(), Error> {
+        for arch in &mut self.arches {
+            if let Object::Mach(Mach::Binary(obj)) = Object::parse(&arch.data)? {
+                if is_signable(obj.header.filetype) {
+                    arch.data = adhoc_sign(&arch.data, options)?;
+                }
             }
         }
-        // Write header
-        // Note that the fat binary header is big-endian, regardless of the
-        // endianness of the contained files.
-        for i in &hdr {
-            writer.write_all(&i.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Plan the layout of the fat binary without writing it
+    ///
+    /// The fat64 format is used when forced with [`FatWriter::set_fat64`] or when
+    /// an offset or size doesn't fit in 32 bits.
+    pub fn plan(&self) -> Result<Layout, Error> {
+        let layout = self.plan_with(self.is_fat64)?;
+        if !layout.fat64
+            && layout
+                .slices
+                .iter()
+                .any(|slice| slice.offset + slice.size > u32::MAX as u64)
+        {
+            return self.plan_with(true);
         }
-        let mut offset = 4 * hdr.len() as i64;
-        // Write each arch
-        for (arch, arch_offset) in self.arches.iter().zip(arch_offsets) {
-            if offset < arch_offset {
-                writer.write_all(&vec![0; (arch_offset - offset) as usize])?;
-                offset = arch_offset;
+        Ok(layout)
+    }
+
+    fn plan_with(&self, fat64: bool) -> Result<Layout, Error> {
+        if self.arches.is_empty() {
+            return Ok(Layout {
+                fat64,
+                header_size: 0,
+                slices: Vec::new(),
+                total_size: 0,
+            });
+        }
+        let sizeof_fat_arch = if fat64 {
+            SIZEOF_FAT_ARCH_64
+        } else {
+            SIZEOF_FAT_ARCH
+        };
+        let header_size = (SIZEOF_FAT_HEADER + self.arches.len() * sizeof_fat_arch) as u64;
+        let mut total_offset = header_size;
+        let mut slices = Vec::with_capacity(self.arches.len());
+        for arch in &self.arches {
+            let align = self.layout_align(arch);
+            if align <= 0 || !(align as u64).is_power_of_two() {
+                return Err(Error::InvalidAlignment {
+                    arch: arch.name().to_string(),
+                    align: align as u64,
+                });
             }
+            let align = align as u64;
+            let offset = match arch.offset.filter(|_| self.explicit_layout) {
+                Some(offset) if offset % align != 0 || offset < total_offset => {
+                    return Err(Error::InvalidMachO(format!(
+                        "offset {:#x} of {} is not aligned to {:#x} or overlaps the previous data ending at {:#x}",
+                        offset,
+                        arch.name(),
+                        align,
+                        total_offset
+                    )));
+                }
+                Some(offset) => offset,
+                // Round up to multiple of align
+                None => total_offset.div_ceil(align) * align,
+            };
+            let size = arch.data.len() as u64;
+            slices.push(SliceLayout {
+                arch: arch.name(),
+                cpu_type: arch.cpu_type,
+                cpu_subtype: arch.cpu_subtype,
+                offset,
+                size,
+                align,
+                padding: offset - total_offset,
+            });
+            total_offset = offset + size;
+        }
+        Ok(Layout {
+            fat64,
+            header_size,
+            slices,
+            total_size: total_offset,
+        })
+    }
+
+    /// Write Mach-O fat binary into the writer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let layout = self.plan()?;
+        writer.write_all(&layout.header())?;
+        for (slice, arch) in layout.slices.iter().zip(&self.arches) {
+            io::copy(&mut io::repeat(0).take(slice.padding), writer)?;
             writer.write_all(&arch.data)?;
-            offset += arch.data.len() as i64;
         }
         Ok(())
     }
 
+    /// Write Mach-O fat binary into a seekable writer, seeking over the padding
+    ///
+    /// Writing to a new file creates a sparse file on filesystems supporting them.
+    /// The padding is not written, so the target must be empty from the current
+    /// position on.
+    pub fn write_to_seekable<W: Write + Seek>(&self, writer: &mut W) -> Result<(), Error> {
+        let layout = self.plan()?;
+        writer.write_all(&layout.header())?;
+        for (slice, arch) in layout.slices.iter().zip(&self.arches) {
+            writer.seek(SeekFrom::Current(slice.padding as i64))?;
+            writer.write_all(&arch.data)?;
+        }
+        Ok(())
+    }
+
+    /// Alignment used to lay out a slice
+    fn layout_align(&self, arch: &ThinArch) -> i64 {
+        if self.explicit_layout {
+            arch.align
+        } else {
+            self.max_align
+        }
+    }
+
     /// Write Mach-O fat binary to a file
+    ///
+    /// Regular files are written with [`FatWriter::write_to_seekable`], anything else
+    /// such as a pipe with [`FatWriter::write_to`].
     pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
-        let file = File::create(path)?;
-        #[cfg(unix)]
-        {
-            let mut perm = file.metadata()?.permissions();
-            perm.set_mode(0o755);
-            file.set_permissions(perm)?;
-        }
+        let file = create_executable(path.as_ref())?;
+        let metadata = file.metadata()?;
         let mut writer = BufWriter::new(file);
-        self.write_to(&mut writer)?;
+        if metadata.is_file() {
+            self.write_to_seekable(&mut writer)?;
+        } else {
+            self.write_to(&mut writer)?;
+        }
+        writer.flush()?;
         Ok(())
     }
 }
 
-fn get_align_from_cpu_types(cpu_type: CpuType, cpu_subtype: CpuSubType) -> i64 {
-    if let Some(arch_name) = get_arch_name_from_types(cpu_type, cpu_subtype) {
-        if let Some((cpu_type, _)) = get_arch_from_flag(arch_name) {
-            match cpu_type {
-                // embedded
-                CPU_TYPE_ARM | CPU_TYPE_ARM64 | CPU_TYPE_ARM64_32 => return 0x4000,
-                // desktop
-                CPU_TYPE_X86_64 | CPU_TYPE_I386 | CPU_TYPE_POWERPC | CPU_TYPE_POWERPC64 => {
-                    return 0x1000
-                }
-                CPU_TYPE_MC680X0 | CPU_TYPE_MC88000 | CPU_TYPE_SPARC | CPU_TYPE_I860
-                | CPU_TYPE_HPPA => return 0x2000,
-                _ => {}
-            }
+/// Create or truncate a file, regular files are made executable on unix
+pub(crate) fn create_executable(path: &Path) -> io::Result<File> {
+    let file = File::create(path)?;
+    #[cfg(unix)]
+    {
+        let metadata = file.metadata()?;
+        if metadata.is_file() {
+            let mut perm = metadata.permissions();
+            perm.set_mode(0o755);
+            file.set_permissions(perm)?;
         }
     }
-    0
+    Ok(file)
+}
+
+/// Whether slices of two platforms may share a fat binary
+///
+/// Mac Catalyst slices can be combined with macOS ones, as in zippered binaries.
+fn is_compatible_platform(a: Platform, b: Platform) -> bool {
+    a == b
+        || matches!(
+            (a, b),
+            (Platform::MacOS, Platform::MacCatalyst) | (Platform::MacCatalyst, Platform::MacOS)
+        )
 }
 
 #[cfg(test)]
@@ -424,6 +719,70 @@ mod tests {
         assert!(fat.add(f1).is_err());
     }
 
+    #[test]
+    fn test_fat_writer_add_incompatible_platform() {
+        use crate::{error::Error, info::Platform};
+
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_arm64_ios").unwrap())
+            .unwrap();
+        let err = fat
+            .add(fs::read("tests/fixtures/thin_arm64_iossim").unwrap())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::IncompatiblePlatform {
+                platform: Platform::IOSSimulator,
+                existing_platform: Platform::IOS,
+                ..
+            }
+        ));
+        let err = fat
+            .add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "x86_64 slice for macos conflicts with arm64 slice for ios"
+        );
+        // Replacing the only iOS slice is fine
+        fat.replace(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+
+        fat.set_mixed_platforms(true);
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.replace(fs::read("tests/fixtures/thin_arm64_ios").unwrap())
+            .unwrap();
+        assert!(matches!(
+            fat.add(fs::read("tests/fixtures/thin_arm64_iossim").unwrap()),
+            Err(Error::DuplicatedArch(_))
+        ));
+    }
+
+    #[test]
+    fn test_fat_writer_add_raw() {
+        use goblin::mach::cputype::{CPU_SUBTYPE_ARM_V7, CPU_TYPE_ARM};
+
+        use crate::cputype::Arch;
+
+        let armv7 = Arch::new(CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7);
+        let firmware = b"firmware image".to_vec();
+        let mut fat = FatWriter::new();
+        assert!(fat.add(firmware.clone()).is_err());
+        assert!(fat.add_raw(armv7, 3, firmware.clone()).is_err());
+        fat.add_raw(armv7, 0x1000, firmware.clone()).unwrap();
+        assert!(fat.add_raw(armv7, 0x1000, firmware.clone()).is_err());
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        assert_eq!(fat.arches(), vec!["armv7", "arm64"]);
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        assert_eq!(reader.extract("armv7"), Some(firmware.as_slice()));
+        assert_eq!(reader.extract_arch(armv7), Some(firmware.as_slice()));
+    }
+
     #[test]
     fn test_fat_writer_add_fat() {
         let mut fat = FatWriter::new();
@@ -466,6 +825,97 @@ mod tests {
         fat.write_to_file("tests/output/fat_bc").unwrap();
     }
 
+    #[cfg(feature = "bitcode")]
+    #[test]
+    fn test_fat_writer_add_raw_llvm_bitcode() {
+        use goblin::mach::cputype::CPU_TYPE_ARM64;
+
+        use crate::bitcode::BitcodeWrapper;
+
+        let wrapped = fs::read("tests/fixtures/thin_arm64.bc").unwrap();
+        let raw = BitcodeWrapper::parse(&wrapped)
+            .unwrap()
+            .bitcode(&wrapped)
+            .to_vec();
+        let mut fat = FatWriter::new();
+        fat.add(raw.clone()).unwrap();
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        assert_eq!(fat.get("arm64").unwrap(), raw.as_slice());
+        assert!(fat.add(raw.clone()).is_err());
+
+        fat.set_wrap_bitcode(true);
+        let data = fat.get("arm64").unwrap();
+        let wrapper = BitcodeWrapper::parse(data).unwrap();
+        assert_eq!(wrapper.cpu_type(), Some(CPU_TYPE_ARM64));
+        assert_eq!(wrapper.bitcode(data), raw.as_slice());
+        assert_eq!(data.len() % 16, 0);
+    }
+
+    #[test]
+    fn test_fat_writer_strip_bitcode() {
+        use crate::strip::has_embedded_bitcode;
+
+        let mut fat = FatWriter::new();
+        fat.add(fs::read("tests/fixtures/thin_x86_64_bitcode").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        fat.strip_bitcode().unwrap();
+        assert!(!has_embedded_bitcode(fat.get("x86_64").unwrap()).unwrap());
+        // Slices without bitcode keep their code signature
+        assert_eq!(
+            fat.get("arm64").unwrap(),
+            fs::read("tests/fixtures/thin_arm64").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fat_writer_strip_bitcode_unsupported() {
+        use crate::strip::has_embedded_bitcode;
+
+        let object = fs::read("tests/fixtures/bitcode_arm64.o").unwrap();
+        assert!(has_embedded_bitcode(&object).unwrap());
+        let x86_64 = fs::read("tests/fixtures/thin_x86_64_bitcode").unwrap();
+        for slice in ["bitcode_arm64.o", "bitcode_arm64.a"] {
+            let mut fat = FatWriter::new();
+            fat.set_mixed_platforms(true);
+            fat.add(x86_64.clone()).unwrap();
+            fat.add(fs::read(format!("tests/fixtures/{}", slice)).unwrap())
+                .unwrap();
+            let err = fat.strip_bitcode().unwrap_err().to_string();
+            assert!(err.contains("is not supported"), "{}", err);
+            // The executable slice is left untouched as well
+            assert_eq!(fat.get("x86_64").unwrap(), x86_64.as_slice());
+        }
+    }
+
+    #[cfg(feature = "codesign")]
+    #[test]
+    fn test_fat_writer_sign_adhoc() {
+        use crate::codesign::SigningOptions;
+
+        let mut fat = FatWriter::new();
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        fat.add(f1.clone()).unwrap();
+        fat.add(f2).unwrap();
+        fat.sign_adhoc(&SigningOptions::new("fat")).unwrap();
+        assert!(fat.get("x86_64").unwrap().len() > f1.len());
+
+        fat.write_to_file("tests/output/fat_signed").unwrap();
+    }
+
+    #[test]
+    fn test_fat_writer_strip_signatures() {
+        let mut fat = FatWriter::new();
+        let f1 = fs::read("tests/fixtures/simplefat").unwrap();
+        fat.add(f1).unwrap();
+        let signed = fat.get("arm64").unwrap().len();
+        fat.strip_signatures().unwrap();
+        assert!(fat.get("arm64").unwrap().len() < signed);
+    }
+
     #[test]
     fn test_fat_writer_remove() {
         let mut fat = FatWriter::new();
@@ -478,4 +928,130 @@ mod tests {
         assert!(fat.exists("x86_64"));
         assert!(!fat.exists("arm64"));
     }
+
+    #[test]
+    fn test_fat_writer_replace() {
+        let mut fat = FatWriter::new();
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        let f2 = fs::read("tests/fixtures/thin_arm64").unwrap();
+        fat.add(f1.clone()).unwrap();
+        fat.add(f2).unwrap();
+        assert_eq!(fat.arches(), vec!["x86_64", "arm64"]);
+        let replaced = fat.replace(f1.clone()).unwrap();
+        assert_eq!(replaced, vec![f1.clone()]);
+        assert_eq!(fat.len(), 2);
+        assert_eq!(fat.get("x86_64"), Some(f1.as_slice()));
+        assert!(fat.get("i386").is_none());
+    }
+
+    #[test]
+    fn test_fat_writer_plan() {
+        let mut fat = FatWriter::new();
+        assert_eq!(fat.plan().unwrap().total_size, 0);
+        fat.add(fs::read("tests/fixtures/thin_x86_64").unwrap())
+            .unwrap();
+        fat.add(fs::read("tests/fixtures/thin_arm64").unwrap())
+            .unwrap();
+        let layout = fat.plan().unwrap();
+        assert!(!layout.fat64);
+        assert_eq!(layout.header_size, 8 + 2 * 20);
+        assert_eq!(layout.slices[0].arch, "x86_64");
+        assert_eq!(layout.slices[0].offset, 0x4000);
+        assert_eq!(layout.slices[0].padding, 0x4000 - 48);
+        assert_eq!(layout.slices[1].align, 0x4000);
+
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(out.len() as u64, layout.total_size);
+        let reader = FatReader::new(&out).unwrap();
+        for (arch, slice) in reader.iter_arches().zip(&layout.slices) {
+            assert_eq!(arch.offset, slice.offset);
+            assert_eq!(arch.size, slice.size);
+            assert_eq!(1u64 << arch.align, slice.align);
+        }
+
+        fat.set_fat64(true);
+        let layout = fat.plan().unwrap();
+        assert!(layout.fat64);
+        assert_eq!(layout.header_size, 8 + 2 * 32);
+    }
+
+    #[test]
+    fn test_fat_writer_write_to_seekable() {
+        use std::io::Cursor;
+
+        let mut fat = FatWriter::new();
+        fat.add_file("tests/fixtures/simplefat").unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+
+        // The padding is seeked over, not written
+        let mut cursor = Cursor::new(vec![0xff; out.len()]);
+        fat.write_to_seekable(&mut cursor).unwrap();
+        let sparse = cursor.into_inner();
+        let layout = fat.plan().unwrap();
+        let mut end = layout.header_size;
+        for slice in &layout.slices {
+            let padding = &sparse[end as usize..slice.offset as usize];
+            assert_eq!(padding.len() as u64, slice.padding);
+            assert!(padding.iter().all(|&byte| byte == 0xff));
+            let range = slice.offset as usize..(slice.offset + slice.size) as usize;
+            assert_eq!(sparse[range.clone()], out[range]);
+            end = slice.offset + slice.size;
+        }
+        assert!(layout.slices.iter().any(|slice| slice.padding > 0));
+
+        fat.write_to_file("tests/output/fat_seekable").unwrap();
+        assert_eq!(fs::read("tests/output/fat_seekable").unwrap(), out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fat_writer_write_to_fifo() {
+        use std::{process::Command, thread};
+
+        let fifo = "tests/output/fat_fifo";
+        let _ = fs::remove_file(fifo);
+        if !Command::new("mkfifo")
+            .arg(fifo)
+            .status()
+            .is_ok_and(|status| status.success())
+        {
+            return;
+        }
+        let mut fat = FatWriter::new();
+        fat.add_file("tests/fixtures/simplefat").unwrap();
+        let reader = thread::spawn(move || fs::read(fifo).unwrap());
+        fat.write_to_file(fifo).unwrap();
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(reader.join().unwrap(), out);
+        fs::remove_file(fifo).unwrap();
+    }
+
+    #[test]
+    fn test_fat_writer_set_align() {
+        let mut fat = FatWriter::new();
+        let f1 = fs::read("tests/fixtures/thin_x86_64").unwrap();
+        fat.add(f1).unwrap();
+        assert!(fat.set_align("x86_64", 0x8000).unwrap());
+        assert!(!fat.set_align("arm64", 0x8000).unwrap());
+        assert!(fat.set_align("x86_64", 3).is_err());
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        let reader = FatReader::new(&out).unwrap();
+        let arch = reader.iter_arches().next().unwrap();
+        assert_eq!(arch.offset, 0x8000);
+        assert_eq!(arch.align, 15);
+
+        fat.set_fat64(true);
+        let mut out = Vec::new();
+        fat.write_to(&mut out).unwrap();
+        assert_eq!(&out[..4], &[0xca, 0xfe, 0xba, 0xbf]);
+
+        // Fat64 inputs
+        let mut fat = FatWriter::new();
+        fat.add(out).unwrap();
+        assert_eq!(fat.arches(), vec!["x86_64"]);
+    }
 }
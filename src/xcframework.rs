@@ -0,0 +1,371 @@
+//! Assembly of `.xcframework` bundles from thin and fat libraries
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bundle::prepare_output_dir,
+    error::Error,
+    info::Platform,
+    info::SliceInfo,
+    write::{create_executable, FatWriter},
+};
+
+/// Platform and variant names used by `.xcframework` bundles
+fn platform_names(platform: Platform) -> Option<(&'static str, Option<&'static str>)> {
+    Some(match platform {
+        Platform::MacOS => ("macos", None),
+        Platform::IOS => ("ios", None),
+        Platform::IOSSimulator => ("ios", Some("simulator")),
+        Platform::MacCatalyst => ("ios", Some("maccatalyst")),
+        Platform::TvOS => ("tvos", None),
+        Platform::TvOSSimulator => ("tvos", Some("simulator")),
+        Platform::WatchOS => ("watchos", None),
+        Platform::WatchOSSimulator => ("watchos", Some("simulator")),
+        Platform::VisionOS => ("xros", None),
+        Platform::VisionOSSimulator => ("xros", Some("simulator")),
+        Platform::DriverKit => ("driverkit", None),
+        Platform::BridgeOS | Platform::Unknown(_) => return None,
+    })
+}
+
+/// A library of an `.xcframework`, one universal binary per platform variant
+#[derive(Debug)]
+pub struct XcframeworkLibrary {
+    pub platform: Platform,
+    pub binary: FatWriter,
+}
+
+impl XcframeworkLibrary {
+    /// Name of the library directory, such as `ios-arm64_x86_64-simulator`
+    pub fn identifier(&self) -> String {
+        let (platform, variant) = platform_names(self.platform).unwrap_or(("unknown", None));
+        let mut identifier = format!("{}-{}", platform, self.architectures().join("_"));
+        if let Some(variant) = variant {
+            identifier.push('-');
+            identifier.push_str(variant);
+        }
+        identifier
+    }
+
+    /// Architectures of the library in alphabetical order
+    pub fn architectures(&self) -> Vec<&'static str> {
+        let mut arches = self.binary.arches();
+        arches.sort_unstable();
+        arches
+    }
+}
+
+/// `.xcframework` bundle builder
+///
+/// Slices are grouped by the platform of their `LC_BUILD_VERSION` or
+/// `LC_VERSION_MIN_*` load command, so that arm64 device and simulator
+/// binaries end up in separate universal binaries.
+#[derive(Debug)]
+pub struct XcframeworkBuilder {
+    library_name: String,
+    headers: Option<PathBuf>,
+    overwrite: bool,
+    libraries: BTreeMap<(&'static str, Option<&'static str>), XcframeworkLibrary>,
+}
+
+impl XcframeworkBuilder {
+    /// Create a builder for libraries named `library_name`, such as `libfoo.a`
+    pub fn new<S: Into<String>>(library_name: S) -> Self {
+        Self {
+            library_name: library_name.into(),
+            headers: None,
+            overwrite: false,
+            libraries: BTreeMap::new(),
+        }
+    }
+
+    /// Copy a headers directory into every library
+    pub fn headers<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.headers = Some(path.into());
+        self
+    }
+
+    /// Replace an existing `.xcframework` directory in [`XcframeworkBuilder::write_to_dir`]
+    pub fn overwrite(&mut self, overwrite: bool) -> &mut Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Add a thin or fat library
+    ///
+    /// Nothing is added if one of the slices is rejected.
+    pub fn add<T: Into<Vec<u8>>>(&mut self, bytes: T) -> Result<(), Error> {
+        let mut input = FatWriter::new();
+        // Slices are split by platform below
+        input.set_mixed_platforms(true);
+        input.add(bytes)?;
+        let mut slices = Vec::with_capacity(input.len());
+        for arch in input.arches() {
+            let data = input.remove(arch).unwrap_or_default();
+            let platform = SliceInfo::parse(0, 0, 0, 0, &data)?
+                .platform
+                .ok_or_else(|| {
+                    Error::InvalidMachO(format!("can't determine the platform of {}", arch))
+                })?;
+            let key = platform_names(platform).ok_or_else(|| {
+                Error::InvalidMachO(format!("unsupported xcframework platform {}", platform))
+            })?;
+            if self
+                .libraries
+                .get(&key)
+                .is_some_and(|library| library.binary.exists(arch))
+            {
+                return Err(Error::DuplicatedArch(arch.to_string()));
+            }
+            slices.push((key, platform, data));
+        }
+        for (key, platform, data) in slices {
+            self.libraries
+                .entry(key)
+                .or_insert_with(|| XcframeworkLibrary {
+                    platform,
+                    binary: FatWriter::new(),
+                })
+                .binary
+                .add(data)?;
+        }
+        Ok(())
+    }
+
+    /// Add a thin or fat library from a file
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.add(fs::read(path)?)
+    }
+
+    /// Libraries grouped by platform variant
+    pub fn libraries(&self) -> impl Iterator<Item = &XcframeworkLibrary> {
+        self.libraries.values()
+    }
+
+    /// Contents of the `Info.plist` describing the libraries
+    pub fn info_plist(&self) -> String {
+        let library_name = escape(&self.library_name);
+        let mut plist = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AvailableLibraries</key>
+	<array>
+"#,
+        );
+        for library in self.libraries() {
+            let (platform, variant) = platform_names(library.platform).unwrap_or(("unknown", None));
+            plist.push_str("\t\t<dict>\n");
+            let _ = writeln!(
+                plist,
+                "\t\t\t<key>BinaryPath</key>\n\t\t\t<string>{}</string>",
+                library_name
+            );
+            if self.headers.is_some() {
+                plist.push_str("\t\t\t<key>HeadersPath</key>\n\t\t\t<string>Headers</string>\n");
+            }
+            let _ = writeln!(
+                plist,
+                "\t\t\t<key>LibraryIdentifier</key>\n\t\t\t<string>{}</string>",
+                library.identifier()
+            );
+            let _ = writeln!(
+                plist,
+                "\t\t\t<key>LibraryPath</key>\n\t\t\t<string>{}</string>",
+                library_name
+            );
+            plist.push_str("\t\t\t<key>SupportedArchitectures</key>\n\t\t\t<array>\n");
+            for arch in library.architectures() {
+                let _ = writeln!(plist, "\t\t\t\t<string>{}</string>", arch);
+            }
+            plist.push_str("\t\t\t</array>\n");
+            let _ = writeln!(
+                plist,
+                "\t\t\t<key>SupportedPlatform</key>\n\t\t\t<string>{}</string>",
+                platform
+            );
+            if let Some(variant) = variant {
+                let _ = writeln!(
+                    plist,
+                    "\t\t\t<key>SupportedPlatformVariant</key>\n\t\t\t<string>{}</string>",
+                    variant
+                );
+            }
+            plist.push_str("\t\t</dict>\n");
+        }
+        plist.push_str(
+            r#"	</array>
+	<key>CFBundlePackageType</key>
+	<string>XFWK</string>
+	<key>XCFrameworkFormatVersion</key>
+	<string>1.0</string>
+</dict>
+</plist>
+"#,
+        );
+        plist
+    }
+
+    /// Write the `.xcframework` directory
+    ///
+    /// An existing directory is only replaced when enabled with
+    /// [`XcframeworkBuilder::overwrite`] and if it is an `.xcframework` itself.
+    pub fn write_to_dir<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        if self.libraries.is_empty() {
+            return Err(Error::InvalidMachO(
+                "xcframework has no libraries".to_string(),
+            ));
+        }
+        if self.overwrite && path.exists() && !is_xcframework(path) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not an xcframework", path.display()),
+            )));
+        }
+        let inputs: Vec<&Path> = self.headers.iter().map(PathBuf::as_path).collect();
+        prepare_output_dir(path, &inputs, self.overwrite)?;
+        for library in self.libraries() {
+            let dir = path.join(library.identifier());
+            fs::create_dir_all(&dir)?;
+            let binary = dir.join(&self.library_name);
+            // Libraries with a single architecture are stored thin
+            match library.binary.iter().next() {
+                Some((_, data)) if library.binary.len() == 1 => {
+                    create_executable(&binary)?.write_all(data)?
+                }
+                _ => library.binary.write_to_file(binary)?,
+            }
+            if let Some(headers) = &self.headers {
+                copy_dir(headers, &dir.join("Headers"))?;
+            }
+        }
+        fs::write(path.join("Info.plist"), self.info_plist())?;
+        Ok(())
+    }
+}
+
+/// Whether a directory looks like an `.xcframework`, going by its `Info.plist`
+fn is_xcframework(path: &Path) -> bool {
+    fs::read_to_string(path.join("Info.plist"))
+        .is_ok_and(|plist| plist.contains("<string>XFWK</string>"))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use goblin::mach::MachO;
+
+    use super::XcframeworkBuilder;
+    use crate::read::FatReader;
+
+    #[test]
+    fn test_xcframework_builder() {
+        let mut builder = XcframeworkBuilder::new("libhello.dylib");
+        builder.add_file("tests/fixtures/thin_x86_64").unwrap();
+        builder.add_file("tests/fixtures/thin_arm64").unwrap();
+        builder
+            .add_file("tests/fixtures/thin_arm64_iossim")
+            .unwrap();
+        builder.add_file("tests/fixtures/thin_arm64_ios").unwrap();
+        let identifiers: Vec<_> = builder.libraries().map(|lib| lib.identifier()).collect();
+        assert_eq!(
+            identifiers,
+            vec!["ios-arm64", "ios-arm64-simulator", "macos-arm64_x86_64"]
+        );
+
+        let plist = builder.info_plist();
+        assert!(plist
+            .contains("<key>LibraryIdentifier</key>\n\t\t\t<string>ios-arm64-simulator</string>"));
+        assert!(
+            plist.contains("<key>SupportedPlatformVariant</key>\n\t\t\t<string>simulator</string>")
+        );
+        assert_eq!(
+            plist.matches("<key>SupportedPlatformVariant</key>").count(),
+            1
+        );
+
+        let output = "tests/output/hello.xcframework";
+        builder.overwrite(true).write_to_dir(output).unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/Info.plist", output)).unwrap(),
+            plist
+        );
+        let macos = fs::read(format!("{}/macos-arm64_x86_64/libhello.dylib", output)).unwrap();
+        assert_eq!(FatReader::new(&macos).unwrap().narches, 2);
+        let ios = fs::read(format!("{}/ios-arm64/libhello.dylib", output)).unwrap();
+        assert!(MachO::parse(&ios, 0).is_ok());
+        #[cfg(unix)]
+        for library in ["macos-arm64_x86_64", "ios-arm64"] {
+            use std::os::unix::fs::PermissionsExt;
+
+            let path = format!("{}/{}/libhello.dylib", output, library);
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755, "{}", library);
+        }
+    }
+
+    #[test]
+    fn test_xcframework_builder_duplicated_arch() {
+        let mut builder = XcframeworkBuilder::new("libhello.dylib");
+        builder.add_file("tests/fixtures/thin_arm64").unwrap();
+        assert!(builder.add_file("tests/fixtures/simplefat").is_err());
+        // The x86_64 slice of the rejected fat binary isn't added either
+        let library = builder.libraries().next().unwrap();
+        assert_eq!(library.architectures(), vec!["arm64"]);
+    }
+
+    #[test]
+    fn test_xcframework_builder_existing_output() {
+        let output = Path::new("tests/output/existing.xcframework");
+        if output.exists() {
+            fs::remove_dir_all(output).unwrap();
+        }
+        let mut builder = XcframeworkBuilder::new("libhello.dylib");
+        builder.add_file("tests/fixtures/thin_arm64").unwrap();
+        builder.write_to_dir(output).unwrap();
+        assert!(builder.write_to_dir(output).is_err());
+        builder.overwrite(true).write_to_dir(output).unwrap();
+
+        // Only xcframeworks are replaced
+        let other = Path::new("tests/output/not_an_xcframework");
+        fs::create_dir_all(other).unwrap();
+        fs::write(other.join("keep"), "").unwrap();
+        assert!(builder.write_to_dir(other).is_err());
+        assert!(other.join("keep").exists());
+
+        // Nor the headers being copied
+        let headers = output.join("Headers");
+        fs::create_dir_all(&headers).unwrap();
+        builder.headers(&headers);
+        assert!(builder.write_to_dir(output).is_err());
+        assert!(headers.exists());
+    }
+}